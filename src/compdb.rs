@@ -0,0 +1,158 @@
+// SPDX-License-Identifier: MIT
+//! Clang compilation databases (`compile_commands.json`): find the one covering
+//! a source file and replay its recorded compiler invocation.
+
+use std::path::{Path, PathBuf};
+
+use crate::normalize_type;
+
+/// One entry of a compilation database.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CompileCommand {
+    /// Directory the command runs from (absolute)
+    pub directory: PathBuf,
+    /// Compiler command line, as recorded
+    pub command: String,
+    pub args: Vec<String>,
+}
+
+/// Nearest `compile_commands.json` above `dir`: at each level the directory
+/// itself, then build directories (`build`, `builddir`, `build-*`,
+/// `cmake-build-*`). The walk stops at the repository root.
+pub fn find_compile_db(dir: &Path) -> Option<PathBuf> {
+    for d in dir.ancestors() {
+        let here = d.join("compile_commands.json");
+        if here.is_file() {
+            return Some(here);
+        }
+        let mut builds: Vec<PathBuf> = std::fs::read_dir(d)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|e| {
+                let n = e.file_name().to_string_lossy().to_string();
+                n.starts_with("build") || n.starts_with("cmake-build")
+            })
+            .map(|e| e.path().join("compile_commands.json"))
+            .filter(|p| p.is_file())
+            .collect();
+        builds.sort();
+        if let Some(db) = builds.into_iter().next() {
+            return Some(db);
+        }
+        if d.join(".git").exists() {
+            break;
+        }
+    }
+    None
+}
+
+/// Entry of database `db` compiling `file` (an absolute path).
+pub fn lookup(db: &Path, file: &Path) -> Option<CompileCommand> {
+    let text = std::fs::read_to_string(db).ok()?;
+    let entries: Vec<serde_json::Value> = serde_json::from_str(&text).ok()?;
+    let want = std::fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+    let db_dir = db.parent().unwrap_or(Path::new("."));
+    entries.iter().find_map(|e| {
+        let directory = db_dir.join(e.get("directory")?.as_str()?);
+        let entry_file = directory.join(e.get("file")?.as_str()?);
+        if std::fs::canonicalize(&entry_file).unwrap_or(entry_file) != want {
+            return None;
+        }
+        let (command, args) = match (e.get("command").and_then(|c| c.as_str()), e.get("arguments")) {
+            (Some(c), _) => (c.to_string(), split_command(c)),
+            (None, Some(serde_json::Value::Array(a))) => {
+                let args: Vec<String> = a.iter().filter_map(|v| v.as_str().map(String::from)).collect();
+                (join_command(&args), args)
+            }
+            _ => return None,
+        };
+        Some(CompileCommand { directory, command, args })
+    })
+}
+
+/// Split a recorded command line into arguments, honoring quotes and backslashes.
+pub fn split_command(s: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut cur = String::new();
+    let mut in_arg = false;
+    let mut quote: Option<char> = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"') | None, '\\') => {
+                if let Some(n) = chars.next() {
+                    cur.push(n);
+                }
+                in_arg = true;
+            }
+            (Some(_), c) => cur.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut cur));
+                    in_arg = false;
+                }
+            }
+            (None, c) => {
+                cur.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(cur);
+    }
+    args
+}
+
+/// Join arguments into a shell command line, quoting where needed.
+pub fn join_command(args: &[String]) -> String {
+    args.iter()
+        .map(|a| {
+            if !a.is_empty() && a.chars().all(|c| c.is_ascii_alphanumeric() || "-_=+/.,:@".contains(c)) {
+                a.clone()
+            } else {
+                format!("\"{}\"", a.replace('\\', "\\\\").replace('"', "\\\""))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The same compiler invocation reduced to a syntax check: no object file
+/// (`-c`, `-o`) and no dependency files (`-M*`), plus `-fsyntax-only`.
+pub fn syntax_only(args: &[String]) -> Vec<String> {
+    let mut out = Vec::with_capacity(args.len() + 1);
+    let mut it = args.iter();
+    while let Some(a) = it.next() {
+        match a.as_str() {
+            "-c" | "-MD" | "-MMD" | "-MP" => {}
+            "-o" | "-MF" | "-MT" | "-MQ" => {
+                it.next();
+            }
+            s if s.starts_with("-o") || s.starts_with("-MF") || s.starts_with("-MT") || s.starts_with("-MQ") => {}
+            _ => out.push(a.clone()),
+        }
+    }
+    out.insert(out.len().min(1), "-fsyntax-only".to_string());
+    out
+}
+
+/// Recorded command for `file` and the directory to run it from. The default
+/// type and `build` replay the command exactly; `syntax` only checks the file.
+pub fn compile_db_command(file: &Path, ty: Option<&str>) -> Option<(String, PathBuf, PathBuf)> {
+    let syntax = match ty.map(normalize_type).as_deref() {
+        None | Some("build") => false,
+        Some("syntax") => true,
+        Some(_) => return None,
+    };
+    let db = find_compile_db(file.parent()?)?;
+    let entry = lookup(&db, file)?;
+    let cmd = if syntax { join_command(&syntax_only(&entry.args)) } else { entry.command };
+    Some((cmd, entry.directory, db))
+}
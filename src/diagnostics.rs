@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: MIT
+//! `--diagnostics`: commands run in the file's directory, so the locations
+//! tools print (`foo.c:12:3`) are rewritten relative to where ruild was
+//! called, and optionally reported as JSON.
+
+use std::path::{Component, Path, PathBuf};
+
+use regex::Regex;
+
+/// What `--diagnostics` does with a command's output.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
+    /// Rewrite locations relative to the caller's directory
+    Relative,
+    /// Also print each diagnostic as a JSON line on stdout; the command's
+    /// own output goes to stderr
+    Json,
+}
+
+impl Mode {
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "relative" => Some(Mode::Relative),
+            "json" => Some(Mode::Json),
+            _ => None,
+        }
+    }
+}
+
+/// A location a tool reported, with the path as rewritten.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: u32,
+    pub column: Option<u32>,
+    /// `error`, `warning`, `note`... when the tool says so
+    pub severity: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "file": self.file,
+            "line": self.line,
+            "column": self.column,
+            "severity": self.severity,
+            "message": self.message,
+        })
+        .to_string()
+    }
+}
+
+/// Rewrites the lines of one output stream. Tools that spread a diagnostic
+/// over several lines (rustc's `-->`, eslint's file headers) need the lines
+/// before, so each stream gets its own rewriter.
+pub struct Rewriter {
+    workdir: PathBuf,
+    cwd: PathBuf,
+    /// `file:line:col: severity: message`: gcc, clang, go, TeX with
+    /// -file-line-error, eslint's unix format
+    re_colon: Regex,
+    /// `--> file:line:col` under rustc's `error[E0308]: message`
+    re_rustc_at: Regex,
+    re_rustc_head: Regex,
+    /// gcc's include trail: `In file included from main.c:2:`
+    re_included: Regex,
+    /// pandoc: `Error at "doc.md" (line 3, column 1):`
+    re_pandoc: Regex,
+    /// eslint's stylish format: `  12:3  error  message  rule` below the file
+    re_eslint: Regex,
+    rustc_head: Option<(String, String)>,
+    eslint_file: Option<String>,
+}
+
+impl Rewriter {
+    /// Paths in the output are relative to `workdir`, where the command ran;
+    /// they are rewritten relative to `cwd`.
+    pub fn new(workdir: &Path, cwd: &Path) -> Self {
+        Rewriter {
+            workdir: std::fs::canonicalize(workdir).unwrap_or_else(|_| workdir.to_path_buf()),
+            cwd: std::fs::canonicalize(cwd).unwrap_or_else(|_| cwd.to_path_buf()),
+            re_colon: Regex::new(
+                r#"^(?P<file>[^\s:][^:]*?):(?P<line>\d+):(?:(?P<col>\d+):)?(?:\s*(?P<sev>fatal error|error|warning|note|info)\s*:)?\s*(?P<msg>.*)$"#,
+            )
+            .unwrap(),
+            re_rustc_at: Regex::new(r#"^(?P<pre>\s*--> )(?P<file>.+?):(?P<line>\d+):(?P<col>\d+)$"#).unwrap(),
+            re_rustc_head: Regex::new(r#"^(error|warning|note)(?:\[\w+\])?: (.*)$"#).unwrap(),
+            re_included: Regex::new(r#"^(?:In file included|\s+) from (?P<file>.+?):\d+[:,]$"#).unwrap(),
+            re_pandoc: Regex::new(r#"^(?P<msg>.*?)"(?P<file>[^"]+)" \(line (?P<line>\d+), column (?P<col>\d+)\)"#).unwrap(),
+            re_eslint: Regex::new(r#"^\s+(\d+):(\d+)\s+(error|warning)\s+(.*?)(?:\s{2,}\S+)?$"#).unwrap(),
+            rustc_head: None,
+            eslint_file: None,
+        }
+    }
+
+    /// `path` as printed by the tool, relative to the caller's directory;
+    /// `None` unless it names an existing file.
+    fn rewrite_path(&self, path: &str) -> Option<String> {
+        let abs = self.workdir.join(path);
+        if !abs.is_file() {
+            return None;
+        }
+        let abs = std::fs::canonicalize(&abs).unwrap_or(abs);
+        Some(relative_to(&abs, &self.cwd).display().to_string())
+    }
+
+    /// The line with its location rewritten, and the diagnostic it reports.
+    pub fn line(&mut self, text: &str) -> (String, Option<Diagnostic>) {
+        if let Some(c) = self.re_rustc_head.captures(text) {
+            self.rustc_head = Some((c[1].to_string(), c[2].to_string()));
+            return (text.to_string(), None);
+        }
+        if let Some(c) = self.re_rustc_at.captures(text) {
+            if let Some(file) = self.rewrite_path(&c["file"]) {
+                let (severity, message) = self.rustc_head.take().unzip();
+                let diag = Diagnostic {
+                    file: file.clone(),
+                    line: c["line"].parse().unwrap_or(0),
+                    column: c["col"].parse().ok(),
+                    severity,
+                    message: message.unwrap_or_default(),
+                };
+                return (format!("{}{}:{}:{}", &c["pre"], file, &c["line"], &c["col"]), Some(diag));
+            }
+        }
+        if let Some(m) = self.re_included.captures(text).and_then(|c| c.name("file")) {
+            if let Some(file) = self.rewrite_path(m.as_str()) {
+                return (format!("{}{}{}", &text[..m.start()], file, &text[m.end()..]), None);
+            }
+        }
+        if let Some(c) = self.re_colon.captures(text) {
+            let m = c.name("file").unwrap();
+            if let Some(file) = self.rewrite_path(m.as_str()) {
+                let diag = Diagnostic {
+                    file: file.clone(),
+                    line: c["line"].parse().unwrap_or(0),
+                    column: c.name("col").and_then(|v| v.as_str().parse().ok()),
+                    severity: c.name("sev").map(|v| v.as_str().to_string()),
+                    message: c["msg"].to_string(),
+                };
+                return (format!("{}{}", file, &text[m.end()..]), Some(diag));
+            }
+        }
+        if let Some(c) = self.re_pandoc.captures(text) {
+            let m = c.name("file").unwrap();
+            if let Some(file) = self.rewrite_path(m.as_str()) {
+                let diag = Diagnostic {
+                    file: file.clone(),
+                    line: c["line"].parse().unwrap_or(0),
+                    column: c["col"].parse().ok(),
+                    severity: Some("error".to_string()),
+                    message: c["msg"].trim().to_string(),
+                };
+                return (format!("{}{}{}", &text[..m.start()], file, &text[m.end()..]), Some(diag));
+            }
+        }
+        if let Some(file) = Some(text.trim_end()).filter(|t| Path::new(t).is_absolute()).and_then(|t| self.rewrite_path(t)) {
+            self.eslint_file = Some(file.clone());
+            return (file, None);
+        }
+        if let (Some(file), Some(c)) = (&self.eslint_file, self.re_eslint.captures(text)) {
+            let diag = Diagnostic {
+                file: file.clone(),
+                line: c[1].parse().unwrap_or(0),
+                column: c[2].parse().ok(),
+                severity: Some(c[3].to_string()),
+                message: c[4].to_string(),
+            };
+            return (text.to_string(), Some(diag));
+        }
+        if text.trim().is_empty() {
+            self.eslint_file = None;
+        }
+        (text.to_string(), None)
+    }
+}
+
+/// `path` relative to `base`, both absolute; `path` itself when they share
+/// no root (another drive on Windows).
+pub fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let p: Vec<Component> = path.components().collect();
+    let b: Vec<Component> = base.components().collect();
+    let common = p.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    if common == 0 {
+        return path.to_path_buf();
+    }
+    let mut rel: PathBuf = b[common..].iter().map(|_| Component::ParentDir).collect();
+    rel.extend(&p[common..]);
+    if rel.as_os_str().is_empty() {
+        rel.push(".");
+    }
+    rel
+}
@@ -0,0 +1,554 @@
+// SPDX-License-Identifier: MIT
+//! `--export`: turn the recipes of files into build files for other tools, so
+//! CI can run them without ruild installed.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+use crate::compdb::split_command;
+use crate::{
+    file_rule_matches, file_workdir, load_defaults_cfg, normalize_type, resolve, resolve_extension, scan_directives, scan_file, FileScan,
+    Resolution, Source,
+};
+
+/// Formats accepted by `--export`.
+pub const FORMATS: &[&str] = &["make", "just", "ninja", "compile_commands", "vscode"];
+
+/// Compilers that write a make-style dependency file with `-MD -MF <file>`.
+const GCC_LIKE: &[&str] = &["cc", "c++", "gcc", "g++", "clang", "clang++"];
+
+/// Directories never descended into when exporting a tree.
+const SKIP_DIRS: &[&str] = &["node_modules", "target"];
+
+/// One resolved recipe, with paths relative to the export root.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    /// File the recipe belongs to
+    pub file: String,
+    pub ty: Option<String>,
+    /// Directory the command runs from, `.` for the root
+    pub dir: String,
+    /// Fully expanded command
+    pub command: String,
+    /// Files the recipe declares through `%<token>` placeholders other than
+    /// the file's own extension
+    pub outputs: Vec<String>,
+}
+
+impl Entry {
+    /// Target name: the file path and type, reduced to `[A-Za-z0-9-]`.
+    pub fn name(&self) -> String {
+        let raw = match &self.ty {
+            Some(t) => format!("{}-{}", self.file, t),
+            None => self.file.clone(),
+        };
+        let mut name = String::new();
+        for c in raw.chars() {
+            if c.is_ascii_alphanumeric() {
+                name.push(c);
+            } else if !name.ends_with('-') {
+                name.push('-');
+            }
+        }
+        let name = name.trim_matches('-').to_string();
+        if name.starts_with(|c: char| c.is_ascii_digit()) { format!("file-{}", name) } else { name }
+    }
+
+    /// The command prefixed with a `cd` into its directory.
+    pub fn shell_line(&self) -> String {
+        if self.dir == "." {
+            return self.command.clone();
+        }
+        let dir = if self.dir.contains(|c: char| c.is_whitespace() || "\"'$&;|".contains(c)) {
+            format!("\"{}\"", self.dir)
+        } else {
+            self.dir.clone()
+        };
+        format!("cd {} && {}", dir, self.command)
+    }
+}
+
+/// Target names of `entries`, in order. Paths that reduce to the same name
+/// (`docs/guide.md`, `docs-guide.md`) get `-2`, `-3`, ... in order.
+pub fn names(entries: &[Entry]) -> Vec<String> {
+    let mut taken: HashSet<String> = HashSet::new();
+    let mut names = Vec::new();
+    for e in entries {
+        let base = e.name();
+        let mut name = base.clone();
+        let mut n = 1;
+        while !taken.insert(name.clone()) {
+            n += 1;
+            name = format!("{}-{}", base, n);
+        }
+        names.push(name);
+    }
+    names
+}
+
+/// Print the recipes of `paths` (files, or directories walked recursively) in
+/// `format`, relative to the current directory. `vscode` writes
+/// `.vscode/tasks.json` instead, replacing an existing one only with `force`.
+pub fn export(format: &str, paths: &[PathBuf], force: bool) -> i32 {
+    let root = std::env::current_dir().and_then(std::fs::canonicalize).unwrap_or_else(|_| PathBuf::from("."));
+    // clangd wants every source, annotated or not
+    let entries = collect(paths, &root, format == "compile_commands");
+    if entries.is_empty() {
+        eprintln!("nothing to export");
+        return 1;
+    }
+    let text = match format {
+        "make" => render_make(&entries),
+        "just" => render_just(&entries),
+        "ninja" => render_ninja(&entries),
+        "compile_commands" => render_compile_commands(&entries, &root),
+        "vscode" => render_vscode(&entries),
+        _ => {
+            eprintln!("--export expects one of {} (got {})", FORMATS.join(", "), format);
+            return 2;
+        }
+    };
+    if format == "vscode" {
+        return match write_tasks(&root, &text, force) {
+            Ok(path) => {
+                println!("wrote {}", path.display());
+                0
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                1
+            }
+        };
+    }
+    print!("{}", text);
+    0
+}
+
+/// Write `text` to `<root>/.vscode/tasks.json`, creating the directory. An
+/// existing file holds the user's own tasks, so it is kept unless `force`.
+pub fn write_tasks(root: &Path, text: &str, force: bool) -> Result<PathBuf, String> {
+    let dir = root.join(".vscode");
+    let path = dir.join("tasks.json");
+    if path.exists() && !force {
+        return Err(format!("{} already exists; pass --force to replace it", path.display()));
+    }
+    std::fs::create_dir_all(&dir).map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+    std::fs::write(&path, text).map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+/// Every file under `paths`, in order, and whether it was named explicitly;
+/// hidden entries are skipped in directories, and so are symlinks to
+/// directories, which may point back up the tree.
+fn walk(paths: &[PathBuf], explicit: bool) -> Vec<(PathBuf, bool)> {
+    let mut files = Vec::new();
+    for p in paths {
+        if !p.is_dir() {
+            files.push((p.clone(), explicit));
+            continue;
+        }
+        let mut children: Vec<PathBuf> = std::fs::read_dir(p)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|e| e.path())
+            .filter(|c| {
+                let name = c.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+                let skipped_dir = c.is_dir() && SKIP_DIRS.contains(&name.as_str());
+                let linked_dir = c.is_dir() && c.symlink_metadata().is_ok_and(|m| m.file_type().is_symlink());
+                !(name.starts_with('.') || name.ends_with(".build") || skipped_dir || linked_dir)
+            })
+            .collect();
+        children.sort();
+        files.extend(walk(&children, false));
+    }
+    files
+}
+
+/// `path` relative to `root` with `/` separators, or absolute outside it.
+fn relative(path: &Path, root: &Path) -> String {
+    let abs = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    match abs.strip_prefix(root) {
+        Ok(rel) if rel.as_os_str().is_empty() => ".".to_string(),
+        Ok(rel) => rel.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/"),
+        Err(_) => abs.display().to_string(),
+    }
+}
+
+fn join_rel(dir: &str, name: &str) -> String {
+    if dir == "." { name.to_string() } else { format!("{}/{}", dir, name) }
+}
+
+/// Types a file declares: its directives', those of `file:` rules matching
+/// it and those of the file it delegates to. `None` when the file carries no
+/// annotation and no `file:` rule matches it.
+fn declared_types(file: &Path, scan: &FileScan) -> Option<Vec<String>> {
+    let workdir = file_workdir(file);
+    let lname = file.file_name().map(|n| n.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+    let rules: Vec<Option<String>> = load_defaults_cfg(Some(&workdir))
+        .map(|cfg| cfg.file_rules.iter().filter(|r| file_rule_matches(r, &lname)).map(|r| r.ty.clone()).collect())
+        .unwrap_or_default();
+    if scan.directives.is_empty() && scan.target.is_none() && scan.tex_program.is_none() && rules.is_empty() {
+        return None;
+    }
+    let mut directives = scan.directives.clone();
+    if let Some((path, _)) = &scan.target {
+        directives.extend(scan_directives(&workdir.join(path)).unwrap_or_default());
+    }
+    let mut types: Vec<String> = directives.iter().filter(|d| !d.ty.is_empty()).map(|d| normalize_type(&d.ty)).collect();
+    types.extend(rules.into_iter().flatten());
+    types.sort();
+    types.dedup();
+    Some(types)
+}
+
+/// Resolve the default recipe and every declared type of each file. Files
+/// found in directories are only exported when annotated, unless `every` is
+/// set; files named on the command line also fall back to extension rules.
+/// Whole-project and compilation-database commands are left to the project's
+/// own build (with `every`, a file inside a project gets its extension rule
+/// instead), and a type that runs the same command as one already exported
+/// is skipped.
+pub fn collect(paths: &[PathBuf], root: &Path, every: bool) -> Vec<Entry> {
+    let re_token = Regex::new(r#"%([A-Za-z0-9]+)"#).unwrap();
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut seen: HashSet<(PathBuf, String)> = HashSet::new();
+    for (file, explicit) in walk(paths, true) {
+        let explicit = explicit || every;
+        let Ok(scan) = scan_file(&file) else { continue };
+        let types = match declared_types(&file, &scan) {
+            Some(types) => types,
+            None if explicit => Vec::new(),
+            None => continue,
+        };
+        for ty in std::iter::once(None).chain(types.iter().map(|t| Some(t.as_str()))) {
+            let Some(mut r) = resolve(ty, &file) else { continue };
+            if matches!(r.source, Source::Project { .. }) && every {
+                let Some(own) = resolve_extension(ty, &file) else { continue };
+                r = own;
+            }
+            if matches!(r.source, Source::Project { .. } | Source::CompileDb { .. }) {
+                continue;
+            }
+            let command = r.command(ty);
+            let Resolution { tpl, base, workdir, file: owner, .. } = r;
+            let owner_abs = std::fs::canonicalize(&owner).unwrap_or_else(|_| owner.clone());
+            if !seen.insert((owner_abs, ty.unwrap_or_default().to_string())) {
+                continue;
+            }
+            let file_rel = relative(&owner, root);
+            if entries.iter().any(|e| e.file == file_rel && e.command == command) {
+                continue;
+            }
+            let dir = relative(&workdir, root);
+            let own_ext = owner.extension().map(|e| e.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+            let mut outputs: Vec<String> = Vec::new();
+            for c in re_token.captures_iter(&tpl) {
+                let out = join_rel(&dir, &format!("{}{}", base, &c[1]));
+                if c[1].to_ascii_lowercase() != own_ext && !outputs.contains(&out) {
+                    outputs.push(out);
+                }
+            }
+            entries.push(Entry { file: file_rel, ty: ty.map(str::to_string), dir, command, outputs });
+        }
+    }
+    entries
+}
+
+/// A Makefile: one phony target per recipe (`all` builds the defaults);
+/// declared outputs are real targets depending on the file, so make only
+/// rebuilds what is out of date.
+pub fn render_make(entries: &[Entry]) -> String {
+    let mut out = String::from("# Generated by `ruild --export make`; re-run it after changing recipes.\n\n");
+    let names = names(entries);
+    let defaults: Vec<&str> = entries.iter().zip(&names).filter(|(e, _)| e.ty.is_none()).map(|(_, n)| n.as_str()).collect();
+    out.push_str(&format!(".PHONY: all {}\n", names.join(" ")));
+    out.push_str(&format!("all: {}\n", defaults.join(" ")));
+    let mut claimed: HashSet<&str> = HashSet::new();
+    for (e, name) in entries.iter().zip(&names) {
+        let recipe = e.shell_line().replace('$', "$$");
+        let label = e.ty.as_ref().map_or_else(|| e.file.clone(), |t| format!("{} -{}", e.file, t));
+        out.push_str(&format!("\n# {}\n", label));
+        let owns_outputs = !e.outputs.is_empty() && e.outputs.iter().all(|o| !claimed.contains(o.as_str()));
+        if owns_outputs {
+            claimed.extend(e.outputs.iter().map(String::as_str));
+            let grouped = if e.outputs.len() > 1 { "&:" } else { ":" };
+            let outputs: Vec<String> = e.outputs.iter().map(|o| make_path(o)).collect();
+            out.push_str(&format!("{}: {}\n", name, outputs.join(" ")));
+            out.push_str(&format!("{}{} {}\n\t{}\n", outputs.join(" "), grouped, make_path(&e.file), recipe));
+        } else {
+            out.push_str(&format!("{}:\n\t{}\n", name, recipe));
+        }
+    }
+    out
+}
+
+/// Escape a path for a make target or prerequisite.
+fn make_path(p: &str) -> String {
+    p.replace('$', "$$").replace(' ', "\\ ").replace(':', "\\:").replace('#', "\\#")
+}
+
+/// A justfile: one recipe per file and type, `all` (the default) builds the
+/// defaults.
+pub fn render_just(entries: &[Entry]) -> String {
+    let mut out = String::from("# Generated by `ruild --export just`; re-run it after changing recipes.\n\n");
+    let names = names(entries);
+    let defaults: Vec<&str> = entries.iter().zip(&names).filter(|(e, _)| e.ty.is_none()).map(|(_, n)| n.as_str()).collect();
+    out.push_str(&format!("# Build every file\nall: {}\n", defaults.join(" ")).replace(" \n", "\n"));
+    for (e, name) in entries.iter().zip(&names) {
+        let label = e.ty.as_ref().map_or_else(|| e.file.clone(), |t| format!("{} -{}", e.file, t));
+        let recipe = e.shell_line().replace("{{", "{{{{");
+        out.push_str(&format!("\n# {}\n{}:\n    {}\n", label, name, recipe));
+    }
+    out
+}
+
+/// Escape a path for a ninja `build` line.
+fn ninja_path(p: &str) -> String {
+    p.replace('$', "$$").replace(' ', "$ ").replace(':', "$:")
+}
+
+/// Program name of a command word, without directory, quotes or a version
+/// suffix (`/usr/bin/gcc-13` is `gcc`).
+fn program(word: &str) -> &str {
+    let prog = word.trim_matches('"').rsplit('/').next().unwrap_or_default();
+    prog.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.').trim_end_matches('-')
+}
+
+fn is_gcc_like(word: &str) -> bool {
+    GCC_LIKE.contains(&program(word))
+}
+
+/// The entry with its command writing a dependency file beside its first
+/// output, and that file relative to the export root. gcc-style compilers
+/// get `-MD -MF`, latexmk `-deps-out`. The command runs in the file's
+/// directory, so the paths it lists are rewritten relative to the root.
+fn with_depfile(e: &Entry) -> Option<(Entry, String)> {
+    let first = e.outputs.first()?;
+    let name = if e.dir == "." { first.as_str() } else { first.strip_prefix(&format!("{}/", e.dir))? };
+    let dep = format!("{}.d", name);
+    let mut words: Vec<String> = e.command.split(' ').map(str::to_string).collect();
+    let tool = words.iter().position(|w| is_gcc_like(w) || program(w) == "latexmk")?;
+    let flags = if program(&words[tool]) == "latexmk" {
+        format!("-deps -deps-out=\"{}\"", dep)
+    } else {
+        format!("-MD -MF \"{}\"", dep)
+    };
+    words.insert(tool + 1, flags);
+    // latexmk comments its list and puts a space before the colon
+    let mut fix = String::from("-e '/^#/d' -e 's@ :@:@'");
+    if e.dir != "." {
+        fix.push_str(&format!(
+            " -e 's@^\\([^/[:space:]\\\\]\\)@{0}/\\1@' -e 's@\\([[:space:]]\\)\\([^/[:space:]\\\\]\\)@\\1{0}/\\2@g'",
+            e.dir
+        ));
+    }
+    let command = format!(
+        "{} && sed {} \"{dep}\" > \"{dep}.tmp\" && mv \"{dep}.tmp\" \"{dep}\"",
+        words.join(" "),
+        fix,
+        dep = dep
+    );
+    Some((Entry { command, ..e.clone() }, format!("{}.d", first)))
+}
+
+/// A build.ninja for the export root: one rule per recipe, and `all` (the
+/// default) builds the defaults. Declared outputs are the edges' outputs with
+/// `restat`, so dependents only rebuild when a tool actually rewrote them;
+/// compilers and latexmk also report the files they read through a depfile.
+pub fn render_ninja(entries: &[Entry]) -> String {
+    let mut out = String::from("# Generated by `ruild --export ninja`; re-run it after changing recipes.\n\n");
+    out.push_str("ninja_required_version = 1.3\n");
+    let names = names(entries);
+    let mut claimed: HashSet<&str> = HashSet::new();
+    for (e, name) in entries.iter().zip(&names) {
+        let label = e.ty.as_ref().map_or_else(|| e.file.clone(), |t| format!("{} -{}", e.file, t));
+        let owns_outputs = !e.outputs.is_empty() && e.outputs.iter().all(|o| !claimed.contains(o.as_str()));
+        let (entry, depfile) = match with_depfile(e).filter(|_| owns_outputs) {
+            Some((entry, depfile)) => (entry, Some(depfile)),
+            None => (e.clone(), None),
+        };
+        out.push_str(&format!("\n# {}\nrule {}\n", label, name));
+        out.push_str(&format!("  command = {}\n  description = {}\n", entry.shell_line().replace('$', "$$"), label.replace('$', "$$")));
+        if owns_outputs {
+            claimed.extend(e.outputs.iter().map(String::as_str));
+            out.push_str("  restat = 1\n");
+            if let Some(depfile) = depfile {
+                out.push_str(&format!("  depfile = {}\n  deps = gcc\n", depfile.replace('$', "$$")));
+            }
+            let outputs: Vec<String> = e.outputs.iter().map(|o| ninja_path(o)).collect();
+            out.push_str(&format!("build {}: {} {}\n", outputs.join(" "), name, ninja_path(&e.file)));
+            out.push_str(&format!("build {}: phony {}\n", name, outputs.join(" ")));
+        } else {
+            out.push_str(&format!("build {}: {} {}\n", name, name, ninja_path(&e.file)));
+        }
+    }
+    let defaults: Vec<&str> = entries.iter().zip(&names).filter(|(e, _)| e.ty.is_none()).map(|(_, n)| n.as_str()).collect();
+    out.push_str(&format!("\nbuild all: phony {}\ndefault all\n", defaults.join(" ")).replace(" \n", "\n"));
+    out
+}
+
+/// The compiler invocation of an entry that compiles its own file: the first
+/// gcc-style command between `&&`, `||`, `;` and `|` naming the file.
+fn compiler_args(e: &Entry) -> Option<Vec<String>> {
+    let name = e.file.rsplit('/').next().unwrap_or(&e.file);
+    let args = split_command(&e.command);
+    args.split(|a| matches!(a.as_str(), "&&" | "||" | ";" | "|"))
+        .find(|cmd| cmd.first().is_some_and(|c| is_gcc_like(c)) && cmd.iter().skip(1).any(|a| a == name || a.ends_with(&format!("/{}", name))))
+        .map(<[String]>::to_vec)
+}
+
+/// A clang compilation database: one entry per C or C++ file whose recipe
+/// runs a gcc-style compiler on it, with the arguments of that recipe (the
+/// default first, else its first type) and the absolute directory it runs in.
+pub fn render_compile_commands(entries: &[Entry], root: &Path) -> String {
+    let mut files: HashSet<&str> = HashSet::new();
+    let mut db: Vec<serde_json::Value> = Vec::new();
+    for e in entries {
+        if files.contains(e.file.as_str()) {
+            continue;
+        }
+        let Some(arguments) = compiler_args(e) else { continue };
+        files.insert(&e.file);
+        db.push(serde_json::json!({
+            "directory": root.join(&e.dir).components().collect::<PathBuf>(),
+            "file": root.join(&e.file).components().collect::<PathBuf>(),
+            "arguments": arguments,
+        }));
+    }
+    format!("{}\n", serde_json::to_string_pretty(&db).unwrap_or_default())
+}
+
+/// Problem matcher for the diagnostics `program` prints: a VS Code built-in
+/// name, or an inline matcher reading paths relative to `dir`.
+fn problem_matcher(program: &str, dir: &str) -> Option<serde_json::Value> {
+    use serde_json::json;
+    let (owner, pattern) = match program {
+        "tsc" => return Some(json!("$tsc")),
+        "eslint" => return Some(json!("$eslint-stylish")),
+        "cc" | "c++" | "gcc" | "g++" | "clang" | "clang++" | "ghc" | "kotlinc" | "swiftc" | "zig" => ("gcc", json!({
+            "regexp": r"^(.*?):(\d+):(\d+):\s+(?:fatal )?(error|warning):\s*(.*)$",
+            "file": 1, "line": 2, "column": 3, "severity": 4, "message": 5,
+        })),
+        "javac" | "mypy" => ("line", json!({
+            "regexp": r"^(.*?):(\d+):\s+(error|warning):\s+(.*)$",
+            "file": 1, "line": 2, "severity": 3, "message": 4,
+        })),
+        "ruff" | "flake8" => ("lint", json!({
+            "regexp": r"^(.*?):(\d+):(\d+):\s+([A-Z]+\d+)\s+(.*)$",
+            "file": 1, "line": 2, "column": 3, "code": 4, "message": 5,
+        })),
+        "go" => ("go", json!({
+            "regexp": r"^(.*?\.go):(\d+):(\d+):\s+(.*)$",
+            "file": 1, "line": 2, "column": 3, "message": 4,
+        })),
+        "nim" => ("nim", json!({
+            "regexp": r"^(.*?)\((\d+), (\d+)\)\s+(Error|Warning):\s+(.*)$",
+            "file": 1, "line": 2, "column": 3, "severity": 4, "message": 5,
+        })),
+        "rustc" => ("rustc", json!([
+            { "regexp": r"^(error|warning)(?:\[\w+\])?: (.*)$", "severity": 1, "message": 2 },
+            { "regexp": r"^\s+--> (.*):(\d+):(\d+)$", "file": 1, "line": 2, "column": 3 },
+        ])),
+        "ocamlopt" => ("ocaml", json!([
+            { "regexp": r#"^File "(.*)", line (\d+), characters (\d+)-\d+:$"#, "file": 1, "line": 2, "column": 3 },
+            { "regexp": r"^(Error|Warning)[^:]*:\s*(.*)$", "severity": 1, "message": 2 },
+        ])),
+        // TeX reports `file:line: message` with -file-line-error
+        "latexmk" | "pdflatex" | "xelatex" | "lualatex" => ("tex", json!({
+            "regexp": r"^(.*?\.tex):(\d+):\s+(.*)$",
+            "file": 1, "line": 2, "message": 3,
+        })),
+        _ => return None,
+    };
+    Some(json!({
+        "owner": format!("ruild-{}", owner),
+        "fileLocation": ["autoDetect", dir],
+        "pattern": pattern,
+    }))
+}
+
+/// Programs an entry runs: the leading words of each command between
+/// `&&`, `||`, `;` and `|`, up to the first option or quoted argument, so
+/// `uv run ruff check` yields `uv`, `run` and `ruff`.
+fn programs(e: &Entry) -> Vec<String> {
+    e.command
+        .split(['&', '|', ';'])
+        .flat_map(|cmd| cmd.split_whitespace().take_while(|w| !w.starts_with(['-', '"', '\''])))
+        .map(|w| program(w).to_string())
+        .collect()
+}
+
+/// A `.vscode/tasks.json` for the export root as workspace folder: one task
+/// per recipe, with problem matchers for the compilers it runs, and a
+/// `ruild: current file` task asking for one of the types found.
+pub fn render_vscode(entries: &[Entry]) -> String {
+    use serde_json::json;
+    let mut tasks: Vec<serde_json::Value> = Vec::new();
+    let mut all_matchers: Vec<serde_json::Value> = Vec::new();
+    for e in entries {
+        let cwd = if e.dir == "." {
+            "${workspaceFolder}".to_string()
+        } else if Path::new(&e.dir).is_absolute() {
+            e.dir.clone()
+        } else {
+            format!("${{workspaceFolder}}/{}", e.dir)
+        };
+        let mut matchers: Vec<serde_json::Value> = Vec::new();
+        for m in programs(e).iter().filter_map(|p| problem_matcher(p, &cwd)) {
+            if !matchers.contains(&m) {
+                matchers.push(m);
+            }
+        }
+        for m in &matchers {
+            let generic = match m {
+                serde_json::Value::Object(o) => {
+                    let mut o = o.clone();
+                    o.insert("fileLocation".into(), json!(["autoDetect", "${fileDirname}"]));
+                    serde_json::Value::Object(o)
+                }
+                other => other.clone(),
+            };
+            if !all_matchers.contains(&generic) {
+                all_matchers.push(generic);
+            }
+        }
+        let label = e.ty.as_ref().map_or_else(|| e.file.clone(), |t| format!("{} -{}", e.file, t));
+        let group = if e.ty.as_deref() == Some("test") { "test" } else { "build" };
+        tasks.push(json!({
+            "label": label,
+            "type": "shell",
+            "command": e.command,
+            "options": { "cwd": cwd },
+            "group": group,
+            "problemMatcher": matchers,
+        }));
+    }
+    let mut types: Vec<&str> = entries.iter().filter_map(|e| e.ty.as_deref()).collect();
+    types.sort();
+    types.dedup();
+    let options: Vec<serde_json::Value> = std::iter::once(json!({ "label": "(default)", "value": "" }))
+        .chain(types.iter().map(|t| json!({ "label": format!("-{}", t), "value": format!("-{}", t) })))
+        .collect();
+    tasks.push(json!({
+        "label": "ruild: current file",
+        "type": "shell",
+        "command": "ruild ${input:ruildType} \"${file}\"",
+        "group": { "kind": "build", "isDefault": true },
+        "problemMatcher": all_matchers,
+    }));
+    let doc = json!({
+        "version": "2.0.0",
+        "tasks": tasks,
+        "inputs": [{
+            "id": "ruildType",
+            "type": "pickString",
+            "description": "Type to build",
+            "options": options,
+            "default": "",
+        }],
+    });
+    format!("{}\n", serde_json::to_string_pretty(&doc).unwrap_or_default())
+}
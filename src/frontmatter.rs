@@ -0,0 +1,151 @@
+// SPDX-License-Identifier: MIT
+//! Front matter recipes: `build:` / `build-<type>:` keys in the YAML (`---`)
+//! or TOML (`+++`) header of Markdown and other documents.
+
+use regex::Regex;
+
+use crate::Directive;
+
+/// Document formats whose files may open with front matter.
+const DOCUMENT_EXTS: &[&str] = &[
+    "md", "markdown", "mdown", "mkd", "mdx", "rmd", "qmd", "pandoc", "html", "htm", "adoc", "asciidoc", "org", "rst", "textile", "txt",
+];
+
+pub fn is_document(ext: &str) -> bool {
+    DOCUMENT_EXTS.iter().any(|e| e.eq_ignore_ascii_case(ext))
+}
+
+/// Recipes declared in the front matter of `text`, in order. A list of
+/// commands runs them in sequence.
+pub fn directives(text: &str) -> Vec<Directive> {
+    let lines: Vec<&str> = text.lines().collect();
+    match lines.first().map(|l| l.trim_end()) {
+        Some("---") => {
+            // pandoc also closes a YAML metadata block with `...`
+            let Some(end) = lines.iter().skip(1).position(|l| matches!(l.trim_end(), "---" | "...")) else {
+                return Vec::new();
+            };
+            yaml_directives(&lines[1..=end])
+        }
+        Some("+++") => {
+            let Some(end) = lines.iter().skip(1).position(|l| l.trim_end() == "+++") else {
+                return Vec::new();
+            };
+            toml_directives(&lines[1..=end])
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Type of a `build` / `build-<type>` key, `None` for other keys.
+fn key_type(key: &str) -> Option<&str> {
+    match key.strip_prefix("build")? {
+        "" => Some(""),
+        rest => rest.strip_prefix('-').filter(|t| !t.is_empty()),
+    }
+}
+
+fn directive(ty: &str, cmds: &[String], line: usize) -> Option<Directive> {
+    let tpl = cmds.iter().map(|c| c.trim()).filter(|c| !c.is_empty()).collect::<Vec<_>>().join(" && ");
+    (!tpl.is_empty()).then(|| Directive { ty: ty.to_string(), tpl, line, sidecar: None })
+}
+
+/// Top-level keys of a YAML block (`body` starts on file line 2). Values are
+/// plain or quoted scalars, `|`/`>` block scalars, or flow/block lists.
+fn yaml_directives(body: &[&str]) -> Vec<Directive> {
+    let re_key = Regex::new(r#"^(build(?:-[A-Za-z0-9_:-]+)?)\s*:(?:\s+(.*?))?\s*$"#).unwrap();
+    let mut found = Vec::new();
+    let mut i = 0;
+    while i < body.len() {
+        let Some(c) = re_key.captures(body[i]) else {
+            i += 1;
+            continue;
+        };
+        let line = i + 2;
+        let Some(ty) = key_type(&c[1]) else {
+            i += 1;
+            continue;
+        };
+        let value = c.get(2).map(|m| m.as_str()).unwrap_or("");
+        // Indented lines that follow belong to this key
+        let nested: Vec<&str> = body[i + 1..]
+            .iter()
+            .take_while(|l| l.trim().is_empty() || l.starts_with([' ', '\t']))
+            .map(|l| l.trim())
+            .collect();
+        i += 1 + nested.len();
+        let cmds: Vec<String> = match value.chars().next() {
+            None => nested.iter().filter_map(|l| l.strip_prefix('-')).map(|l| yaml_scalar(l.trim())).collect(),
+            Some('|') => nested.iter().filter(|l| !l.is_empty()).map(|l| l.to_string()).collect(),
+            Some('>') => vec![nested.iter().filter(|l| !l.is_empty()).copied().collect::<Vec<_>>().join(" ")],
+            Some('[') => flow_list(value),
+            Some(_) => vec![yaml_scalar(value)],
+        };
+        found.extend(directive(ty, &cmds, line));
+    }
+    found
+}
+
+/// A YAML scalar without its quotes; `#` comments end plain scalars.
+fn yaml_scalar(v: &str) -> String {
+    if let Some(s) = v.strip_prefix('"').and_then(|s| s.rsplit_once('"')).map(|(s, _)| s) {
+        return s.replace("\\\"", "\"").replace("\\\\", "\\");
+    }
+    if let Some(s) = v.strip_prefix('\'').and_then(|s| s.rsplit_once('\'')).map(|(s, _)| s) {
+        return s.replace("''", "'");
+    }
+    match v.find(" #") {
+        Some(at) => v[..at].trim().to_string(),
+        None => v.to_string(),
+    }
+}
+
+/// Items of a `[a, "b, c"]` flow list.
+fn flow_list(v: &str) -> Vec<String> {
+    let inner = v.trim().trim_start_matches('[').trim_end_matches(']');
+    let mut items = Vec::new();
+    let mut cur = String::new();
+    let mut quote: Option<char> = None;
+    for c in inner.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => {
+                quote = None;
+                cur.push(c);
+            }
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                cur.push(c);
+            }
+            (None, ',') => items.push(yaml_scalar(std::mem::take(&mut cur).trim())),
+            _ => cur.push(c),
+        }
+    }
+    if !cur.trim().is_empty() {
+        items.push(yaml_scalar(cur.trim()));
+    }
+    items
+}
+
+/// Top-level `build` keys of a TOML block; each key's line is looked up in
+/// the block, which starts on file line 2.
+fn toml_directives(body: &[&str]) -> Vec<Directive> {
+    let Ok(table) = body.join("\n").parse::<toml::Table>() else {
+        return Vec::new();
+    };
+    let mut found: Vec<Directive> = table
+        .iter()
+        .filter_map(|(key, value)| {
+            let ty = key_type(key)?;
+            let cmds: Vec<String> = match value {
+                toml::Value::String(s) => vec![s.clone()],
+                toml::Value::Array(a) => a.iter().filter_map(|v| v.as_str().map(String::from)).collect(),
+                _ => return None,
+            };
+            let re_line = Regex::new(&format!(r#"^\s*"?{}"?\s*="#, regex::escape(key))).unwrap();
+            let line = body.iter().position(|l| re_line.is_match(l)).map_or(1, |i| i + 2);
+            directive(ty, &cmds, line)
+        })
+        .collect();
+    found.sort_by_key(|d| d.line);
+    found
+}
@@ -0,0 +1,414 @@
+// SPDX-License-Identifier: MIT
+//! `ruild lsp`: a language server over stdio for `@build` directives. It
+//! scans buffers and resolves commands with the same code and config as the
+//! CLI: diagnostics for malformed directives and unknown `{{variables}}`,
+//! hover with the expanded command, completion and a code lens per directive.
+
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use serde_json::{json, Value};
+
+use crate::{base_and_ext, detect, expand_command, ext_key, file_workdir, is_comment, known_types, load_defaults_cfg, normalize_type, resolve_text, scan_text};
+
+/// `{{variables}}` understood by `expand_vars`, with what they expand to.
+pub const VARIABLES: &[(&str, &str)] = &[
+    ("file", "quoted file name"),
+    ("file_stem", "quoted file name without extension"),
+    ("dir", "quoted directory of the file"),
+    ("root", "quoted root of the enclosing project"),
+    ("pm", "package manager (npm, yarn, pnpm, bun, deno)"),
+    ("pm_run", "prefix running a package script"),
+    ("pm_start", "package manager start command"),
+    ("pm_test", "package manager test command"),
+    ("pm_install", "package manager install command"),
+    ("py", "Python interpreter of the project"),
+    ("py_run", "prefix running a Python tool in the project's environment"),
+    ("go_root", "quoted root of the Go module"),
+    ("go_pkg", "Go package path of the file"),
+    ("type", "requested type, normalized"),
+];
+
+/// Command run by the code lenses.
+const RUN_COMMAND: &str = "ruild.run";
+
+/// Serve over stdio until the client says `exit` or closes stdin.
+pub fn serve() -> i32 {
+    serve_on(std::io::stdin().lock(), &mut std::io::stdout())
+}
+
+/// Serve the messages framed in `input`, writing replies to `output`, until
+/// the client says `exit` or the input ends.
+pub fn serve_on(mut input: impl BufRead, output: &mut impl Write) -> i32 {
+    let mut server = Server::default();
+    while let Some(msg) = read_message(&mut input) {
+        for out in server.handle(&msg) {
+            write_message(output, &out);
+        }
+        if let Some(code) = server.exit {
+            return code;
+        }
+    }
+    if server.shutdown { 0 } else { 1 }
+}
+
+/// One `Content-Length` framed JSON-RPC message; `None` at end of input.
+fn read_message(input: &mut impl BufRead) -> Option<Value> {
+    loop {
+        let mut len: Option<usize> = None;
+        loop {
+            let mut header = String::new();
+            if input.read_line(&mut header).ok()? == 0 {
+                return None;
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    len = value.trim().parse().ok();
+                }
+            }
+        }
+        let Some(len) = len else { continue };
+        let mut body = vec![0; len];
+        input.read_exact(&mut body).ok()?;
+        if let Ok(msg) = serde_json::from_slice(&body) {
+            return Some(msg);
+        }
+    }
+}
+
+fn write_message(out: &mut impl Write, msg: &Value) {
+    let body = msg.to_string();
+    let _ = write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let _ = out.flush();
+}
+
+/// Send `msg` to the client from outside the message loop.
+fn send(msg: &Value) {
+    write_message(&mut std::io::stdout().lock(), msg);
+}
+
+/// Open documents and lifecycle state.
+#[derive(Default)]
+pub struct Server {
+    docs: HashMap<String, String>,
+    shutdown: bool,
+    /// Exit code once the client sent `exit`
+    exit: Option<i32>,
+}
+
+impl Server {
+    /// Messages to send in reply to `msg`: a response to a request, and
+    /// notifications such as published diagnostics.
+    pub fn handle(&mut self, msg: &Value) -> Vec<Value> {
+        let method = msg["method"].as_str().unwrap_or("");
+        let params = &msg["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+        let pos = (
+            params["position"]["line"].as_u64().unwrap_or(0) as usize,
+            params["position"]["character"].as_u64().unwrap_or(0) as usize,
+        );
+        let result = match method {
+            "initialize" => capabilities(),
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            }
+            "exit" => {
+                self.exit = Some(if self.shutdown { 0 } else { 1 });
+                return Vec::new();
+            }
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("").to_string();
+                self.docs.insert(uri.clone(), text);
+                return vec![self.publish(&uri)];
+            }
+            "textDocument/didChange" => {
+                // Full sync: the last change holds the whole text
+                if let Some(text) = params["contentChanges"].as_array().and_then(|c| c.last()).and_then(|c| c["text"].as_str()) {
+                    self.docs.insert(uri.clone(), text.to_string());
+                }
+                return vec![self.publish(&uri)];
+            }
+            "textDocument/didClose" => {
+                self.docs.remove(&uri);
+                return vec![notification("textDocument/publishDiagnostics", json!({ "uri": uri, "diagnostics": [] }))];
+            }
+            "textDocument/hover" => self.with_doc(&uri, |path, text| hover(path, text, pos)),
+            "textDocument/completion" => self.with_doc(&uri, |path, text| Value::Array(completion(path, text, pos))),
+            "textDocument/codeLens" => self.with_doc(&uri, |_, text| Value::Array(code_lenses(&uri, text))),
+            "workspace/executeCommand" => {
+                let args = &params["arguments"];
+                if params["command"] == RUN_COMMAND {
+                    if let Some(path) = args[0].as_str().and_then(uri_to_path) {
+                        run_in_background(path, args[1].as_str().map(str::to_string));
+                    }
+                }
+                Value::Null
+            }
+            _ => {
+                if msg.get("id").is_some() && !method.is_empty() {
+                    return vec![json!({
+                        "jsonrpc": "2.0",
+                        "id": msg["id"],
+                        "error": { "code": -32601, "message": format!("unsupported method {}", method) },
+                    })];
+                }
+                return Vec::new();
+            }
+        };
+        match msg.get("id") {
+            Some(id) => vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })],
+            None => Vec::new(),
+        }
+    }
+
+    /// Result of `f` on an open document, `null` for unknown ones.
+    fn with_doc(&self, uri: &str, f: impl FnOnce(&Path, &str) -> Value) -> Value {
+        match (uri_to_path(uri), self.docs.get(uri)) {
+            (Some(path), Some(text)) => f(&path, text),
+            _ => Value::Null,
+        }
+    }
+
+    fn publish(&self, uri: &str) -> Value {
+        let diags = self.docs.get(uri).map(|text| diagnostics(text)).unwrap_or_default();
+        notification("textDocument/publishDiagnostics", json!({ "uri": uri, "diagnostics": diags }))
+    }
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+fn capabilities() -> Value {
+    json!({
+        "capabilities": {
+            "textDocumentSync": 1,
+            "hoverProvider": true,
+            "completionProvider": { "triggerCharacters": ["-", "{", "%", " "] },
+            "codeLensProvider": { "resolveProvider": false },
+            "executeCommandProvider": { "commands": [RUN_COMMAND] },
+        },
+        "serverInfo": { "name": "ruild", "version": env!("CARGO_PKG_VERSION") },
+    })
+}
+
+/// Path of a `file://` URI, percent-decoded.
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    let bytes = rest.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%').then(|| rest.get(i + 1..i + 3)).flatten().and_then(|h| u8::from_str_radix(h, 16).ok());
+        match hex {
+            Some(b) => {
+                out.push(b);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    let path = String::from_utf8_lossy(&out).into_owned();
+    // `file:///C:/x` on Windows
+    let path = match path.as_bytes() {
+        [b'/', _, b':', ..] if cfg!(windows) => path[1..].to_string(),
+        _ => path,
+    };
+    Some(PathBuf::from(path))
+}
+
+/// Column of byte offset `byte` in `line`, in UTF-16 code units as LSP counts.
+fn utf16_col(line: &str, byte: usize) -> usize {
+    line[..byte.min(line.len())].encode_utf16().count()
+}
+
+/// Byte offset of UTF-16 column `col` in `line`.
+fn byte_offset(line: &str, col: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= col {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+fn range(line: usize, text: &str, start: usize, end: usize) -> Value {
+    json!({
+        "start": { "line": line, "character": utf16_col(text, start) },
+        "end": { "line": line, "character": utf16_col(text, end) },
+    })
+}
+
+/// Byte offset of the `@build` keyword of a comment line: at the start of a
+/// word, so addresses like `me@buildhost` don't count.
+fn keyword_at(line: &str) -> Option<usize> {
+    is_comment(line)?;
+    line.match_indices("@build").map(|(i, _)| i).find(|&i| i == 0 || line[..i].ends_with(|c: char| c.is_whitespace() || "#/*-!(%".contains(c)))
+}
+
+/// Problems in the directives of `text`: `@build` lines `detect` can't read,
+/// and `{{variables}}` that `expand_vars` would leave as they are.
+pub fn diagnostics(text: &str) -> Vec<Value> {
+    let re_var = Regex::new(r#"\{\{([^{}]*)\}\}"#).unwrap();
+    let mut out = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let Some(at) = keyword_at(line) else { continue };
+        if detect(line).is_none_or(|(_, cmd)| cmd.trim().is_empty()) {
+            let end = line[at..].find(char::is_whitespace).map_or(line.len(), |n| at + n);
+            out.push(json!({
+                "range": range(i, line, at, end),
+                "severity": 1,
+                "source": "ruild",
+                "message": "malformed directive: expected `@build <command>` or `@build-<type> <command>` (types are letters and digits)",
+            }));
+            continue;
+        }
+        for c in re_var.captures_iter(&line[at..]) {
+            let name = c[1].trim();
+            if VARIABLES.iter().any(|(v, _)| *v == name) {
+                continue;
+            }
+            let m = c.get(0).unwrap();
+            let known: Vec<String> = VARIABLES.iter().map(|(v, _)| format!("{{{{{}}}}}", v)).collect();
+            out.push(json!({
+                "range": range(i, line, at + m.start(), at + m.end()),
+                "severity": 2,
+                "source": "ruild",
+                "message": format!("unknown variable {{{{{}}}}}, left as is; known: {}", name, known.join(" ")),
+            }));
+        }
+    }
+    out
+}
+
+/// The command a directive on the hovered line runs and where it runs.
+pub fn hover(path: &Path, text: &str, (line, _): (usize, usize)) -> Value {
+    let scan = scan_text(path, text);
+    let Some(d) = scan.directives.iter().find(|d| d.sidecar.is_none() && d.line == line + 1) else {
+        return Value::Null;
+    };
+    let workdir = file_workdir(path);
+    let base = base_and_ext(path).0;
+    let ty = (!d.ty.is_empty()).then_some(d.ty.as_str());
+    let cmd = expand_command(&d.tpl, &base, &workdir, path, ty);
+    let label = ty.map_or_else(|| "build".to_string(), |t| format!("build -{}", normalize_type(t)));
+    json!({
+        "contents": {
+            "kind": "markdown",
+            "value": format!("**{}** runs in `{}`\n\n```sh\n{}\n```", label, workdir.display(), cmd),
+        },
+    })
+}
+
+fn item(label: &str, kind: u32, detail: String, insert: String) -> Value {
+    json!({ "label": label, "kind": kind, "detail": detail, "insertText": insert })
+}
+
+/// Completion inside a directive: types after `@build-`, variables after
+/// `{{`, `%<token>` placeholders after `%`, and the extension defaults after
+/// `@build ` with no command yet.
+pub fn completion(path: &Path, text: &str, (line, col): (usize, usize)) -> Vec<Value> {
+    let Some(current) = text.lines().nth(line) else { return Vec::new() };
+    let prefix = &current[..byte_offset(current, col)];
+    let Some(at) = keyword_at(prefix) else { return Vec::new() };
+    let directive = &prefix[at..];
+    let workdir = file_workdir(path);
+    let (base, ext) = base_and_ext(path);
+    let lext = ext.to_ascii_lowercase();
+    let cfg = load_defaults_cfg(Some(&workdir));
+
+    if Regex::new(r#"\{\{\w*$"#).unwrap().is_match(directive) {
+        return VARIABLES.iter().map(|(v, doc)| item(v, 6, doc.to_string(), format!("{}}}}}", v))).collect();
+    }
+    if Regex::new(r#"^@build-[A-Za-z0-9]*$"#).unwrap().is_match(directive) {
+        let Some(cfg) = cfg else { return Vec::new() };
+        let scan = scan_text(path, text);
+        return known_types(&cfg, &scan.directives, path, &workdir)
+            .iter()
+            .map(|t| {
+                let detail = resolve_text(Some(t), path, text).map(|r| r.command(Some(t))).unwrap_or_default();
+                item(t, 20, detail, format!("{} ", t))
+            })
+            .collect();
+    }
+    if Regex::new(r#"%[A-Za-z0-9]*$"#).unwrap().is_match(directive) {
+        let re_token = Regex::new(r#"%([A-Za-z0-9]+)"#).unwrap();
+        // The file's own extension, then what its extension rules produce
+        let mut tokens: Vec<String> = [lext.clone(), "out".to_string()].into_iter().filter(|t| !t.is_empty()).collect();
+        for (key, rule) in cfg.iter().flat_map(|c| c.ext_map.iter()) {
+            if lext.is_empty() || (*key != lext && !key.starts_with(&format!("{} -", lext))) {
+                continue;
+            }
+            for c in re_token.captures_iter(&rule.cmd) {
+                if !tokens.iter().any(|t| t == &c[1]) {
+                    tokens.push(c[1].to_string());
+                }
+            }
+        }
+        return tokens.iter().map(|t| item(t, 21, format!("\"{}{}\"", base, t), t.clone())).collect();
+    }
+    if let Some(c) = Regex::new(r#"^@build(?:-([A-Za-z0-9]+))?\s+$"#).unwrap().captures(directive) {
+        let key = ext_key(&lext, c.get(1).map(|t| normalize_type(t.as_str())).as_deref());
+        return cfg
+            .and_then(|cfg| cfg.ext_map.get(&key).cloned())
+            .map(|rule| vec![item(&rule.cmd, 12, format!("`{}` default ({})", key, rule.origin), rule.cmd.clone())])
+            .unwrap_or_default();
+    }
+    Vec::new()
+}
+
+/// A lens above each directive running it, like `ruild [-type] <file>`.
+pub fn code_lenses(uri: &str, text: &str) -> Vec<Value> {
+    let Some(path) = uri_to_path(uri) else { return Vec::new() };
+    scan_text(&path, text)
+        .directives
+        .iter()
+        .filter(|d| d.sidecar.is_none())
+        .map(|d| {
+            let ty = (!d.ty.is_empty()).then(|| normalize_type(&d.ty));
+            let title = ty.as_ref().map_or_else(|| "▶ build".to_string(), |t| format!("▶ build -{}", t));
+            let line = d.line - 1;
+            json!({
+                "range": { "start": { "line": line, "character": 0 }, "end": { "line": line, "character": 0 } },
+                "command": { "title": title, "command": RUN_COMMAND, "arguments": [uri, ty] },
+            })
+        })
+        .collect()
+}
+
+/// Build `path` with this binary, as `ruild [-type] <file>` would, and report
+/// the output in the client's log.
+fn run_in_background(path: PathBuf, ty: Option<String>) {
+    std::thread::spawn(move || {
+        let exe = std::env::current_exe().unwrap_or_else(|_| PathBuf::from("ruild"));
+        let mut cmd = std::process::Command::new(exe);
+        if let Some(t) = &ty {
+            cmd.arg(format!("-{}", t));
+        }
+        let label = format!("ruild {}{}", ty.map(|t| format!("-{} ", t)).unwrap_or_default(), path.display());
+        let (kind, summary, log) = match cmd.arg(&path).output() {
+            Ok(out) => {
+                let log = format!("{}{}", String::from_utf8_lossy(&out.stdout), String::from_utf8_lossy(&out.stderr));
+                match out.status.success() {
+                    true => (3, format!("{}: done", label), log),
+                    false => (1, format!("{}: failed ({})", label, out.status), log),
+                }
+            }
+            Err(e) => (1, format!("{}: {}", label, e), String::new()),
+        };
+        send(&notification("window/logMessage", json!({ "type": 4, "message": format!("$ {}\n{}", label, log) })));
+        send(&notification("window/showMessage", json!({ "type": kind, "message": summary })));
+    });
+}
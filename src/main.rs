@@ -8,7 +8,21 @@ use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, OnceLock};
+
+mod compdb;
+mod diagnostics;
+mod export;
+mod frontmatter;
+mod lsp;
+mod makefile;
+mod modeline;
+mod notebook;
+mod project;
+
+use compdb::compile_db_command;
+use project::{find_project_root, go_package, pick_python_runner, project_command};
 
 #[cfg(target_os = "macos")]
 const BUNDLED_DEFAULTS: &str = include_str!("../defaults/macos.defaults");
@@ -17,6 +31,34 @@ const BUNDLED_DEFAULTS: &str = include_str!("../defaults/unix.defaults");
 #[cfg(windows)]
 const BUNDLED_DEFAULTS: &str = include_str!("../defaults/windows.defaults");
 
+/// Bundled defaults for every platform, for `--dump_defaults --platform <name>`.
+const BUNDLED_MACOS: &str = include_str!("../defaults/macos.defaults");
+const BUNDLED_UNIX: &str = include_str!("../defaults/unix.defaults");
+const BUNDLED_WINDOWS: &str = include_str!("../defaults/windows.defaults");
+
+/// Platform selected with `--platform`, overriding the host for config sections.
+static PLATFORM_OVERRIDE: OnceLock<Platform> = OnceLock::new();
+
+/// The host platform, worked out once: finding the host name may run `hostname`.
+static HOST_PLATFORM: OnceLock<Platform> = OnceLock::new();
+
+/// Set by `--diagnostics`: capture command output and rewrite its locations.
+static DIAGNOSTICS: OnceLock<diagnostics::Mode> = OnceLock::new();
+
+/// Config messages printed so far. The config is read again for each file and
+/// type, but a message is only worth printing once per run.
+static REPORTED: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+
+/// Print `msg` on stderr unless it was already printed; `true` if it was new.
+fn report_once(msg: String) -> bool {
+    let mut reported = REPORTED.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+    let new = reported.insert(msg.clone());
+    if new {
+        eprintln!("{}", msg);
+    }
+    new
+}
+
 fn is_comment(line: &str) -> Option<String> {
     let s = line.trim();
 
@@ -39,9 +81,33 @@ fn is_comment(line: &str) -> Option<String> {
     if let Some(c) = Regex::new(r#"^\(\*\s*(.*?)\s*\*\)$"#).unwrap().captures(s) {
         return Some(c.get(1).unwrap().as_str().to_string());
     }
+    // % comment (TeX, Erlang, MATLAB)
+    if let Some(c) = Regex::new(r#"^%+\s*(.*)$"#).unwrap().captures(s) {
+        return Some(c.get(1).unwrap().as_str().to_string());
+    }
     None
 }
 
+/// Returns (key, value) for a TeX magic comment such as `% !TEX root = main.tex`.
+/// Keys are lowercased; TeXShop's `TS-program` counts as `program`.
+fn tex_magic(line: &str) -> Option<(String, String)> {
+    let re = Regex::new(r#"(?i)^\s*%+\s*!\s*TEX\s+(?:TS-)?(root|program)\s*=\s*(.*?)\s*$"#).unwrap();
+    let caps = re.captures(line)?;
+    Some((caps[1].to_ascii_lowercase(), caps[2].to_string()))
+}
+
+/// Recipe for a `% !TEX program = <name>` magic comment.
+fn tex_program_command(program: &str) -> String {
+    let latexmk = |mode: &str| format!("latexmk {} -interaction=nonstopmode %tex", mode);
+    match program.to_ascii_lowercase().as_str() {
+        "pdflatex" | "pdftex" => latexmk("-pdf"),
+        "xelatex" | "xetex" => latexmk("-pdfxe"),
+        "lualatex" | "luatex" => latexmk("-pdflua"),
+        "latex" => latexmk("-dvi"),
+        other => format!("{} %tex", other),
+    }
+}
+
 /// Returns (type, command) if the line carries a @build directive
 fn detect(line: &str) -> Option<(String, String)> {
     let content = is_comment(line)?;
@@ -68,74 +134,35 @@ fn expand_template(template: &str, base: &str) -> String {
     Regex::new(r#"%"#).unwrap().replace_all(&t, base).to_string()
 }
 
-/// Read defaults from ~/.config/build.defaults, format:
-///   <ext> : <command>
-/// Returns the command for the given extension if found.
-fn read_defaults(ext: &str) -> Option<String> {
-    let p = config_path()?;
-
-    // Ensure a defaults file exists; if not, bootstrap it.
-    if !p.exists() {
-        if let Err(e) = ensure_bootstrap_defaults(&p) {
-            eprintln!(
-                "could not create default settings at {}: {}",
-                p.display(),
-                e
-            );
-            return None;
-        } else {
-            println!("created default settings at {}", p.display());
-        }
-    }
-
-    let fh = match File::open(&p) {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("failed to open {}: {}", p.display(), e);
-            return None;
-        }
-    };
-
-    let re = Regex::new(r#"^([A-Za-z0-9]+)\s*:\s*(.*)$"#).unwrap();
-    let want = ext.to_ascii_lowercase();
-
-    for line in BufReader::new(fh).lines().flatten() {
-        if let Some(c) = re.captures(&line) {
-            let lext = c.get(1).unwrap().as_str().to_ascii_lowercase();
-            let lbuild = c.get(2).unwrap().as_str().to_string();
-            if lext == want {
-                return Some(lbuild);
-            }
-        }
-    }
-    None
+/// Expand `%` placeholders, then `{{variables}}`, into the final shell command line.
+fn expand_command(build_tpl: &str, base: &str, workdir: &Path, filename: &Path, ty: Option<&str>) -> String {
+    let cmdline = expand_template(build_tpl, base);
+    expand_vars(cmdline, filename, workdir, ty)
 }
 
-/// Build command runner: expands placeholders then executes via the platform shell.
+/// Build command runner: executes an expanded command line via the platform shell.
 /// Mirrors `os.execute` behavior by invoking sh -c / cmd /C.
-fn run_command(build_tpl: &str, base: &str, workdir: &Path, filename: &Path, ty: Option<&str>) -> bool {
-    let cmdline = expand_template(build_tpl, base);
-    let cmdline = expand_vars(cmdline, filename, workdir, ty);
-    println!("Running: {}", cmdline);
-
-    let status = if cfg!(windows) {
-        Command::new("cmd")
-            .arg("/C")
-            .arg(cmdline)
-            .current_dir(workdir)
-            .stdin(Stdio::inherit())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status()
+fn run_command(cmdline: &str, workdir: &Path) -> bool {
+    let mode = DIAGNOSTICS.get().copied();
+    if mode == Some(diagnostics::Mode::Json) {
+        eprintln!("Running: {}", cmdline);
+    } else {
+        println!("Running: {}", cmdline);
+    }
+
+    let mut cmd = if cfg!(windows) {
+        let mut c = Command::new("cmd");
+        c.arg("/C");
+        c
     } else {
-        Command::new("sh")
-            .arg("-c")
-            .arg(cmdline)
-            .current_dir(workdir)
-            .stdin(Stdio::inherit())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status()
+        let mut c = Command::new("sh");
+        c.arg("-c");
+        c
+    };
+    cmd.arg(cmdline).current_dir(workdir).stdin(Stdio::inherit());
+    let status = match mode {
+        Some(mode) => run_rewriting(cmd, workdir, mode),
+        None => cmd.stdout(Stdio::inherit()).stderr(Stdio::inherit()).status(),
     };
 
     match status {
@@ -147,27 +174,78 @@ fn run_command(build_tpl: &str, base: &str, workdir: &Path, filename: &Path, ty:
     }
 }
 
+/// Run `cmd` with its output captured line by line, locations rewritten
+/// relative to the caller's directory. In JSON mode each diagnostic is printed
+/// to stdout and the output itself goes to stderr.
+fn run_rewriting(mut cmd: Command, workdir: &Path, mode: diagnostics::Mode) -> std::io::Result<std::process::ExitStatus> {
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let json = mode == diagnostics::Mode::Json;
+    let pump = |stream: Box<dyn std::io::Read + Send>, to_stderr: bool| {
+        let mut rewriter = diagnostics::Rewriter::new(workdir, &cwd);
+        move || {
+            for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                let (text, diag) = rewriter.line(&line);
+                if to_stderr || json {
+                    eprintln!("{}", text);
+                } else {
+                    println!("{}", text);
+                }
+                if let Some(d) = diag.filter(|_| json) {
+                    println!("{}", d.to_json());
+                }
+            }
+        }
+    };
+    let out = child.stdout.take().map(|s| pump(Box::new(s), false));
+    let err = child.stderr.take().map(|s| pump(Box::new(s), true));
+    std::thread::scope(|scope| {
+        if let Some(out) = out {
+            scope.spawn(out);
+        }
+        if let Some(err) = err {
+            scope.spawn(err);
+        }
+    });
+    child.wait()
+}
+
 /// Additional variable expansion on top of % placeholders for project-aware rules.
 /// Supported variables:
 ///   {{file}}      -> quoted file name (no path)
 ///   {{file_stem}} -> quoted file stem
 ///   {{dir}}       -> quoted directory path of the file
-///   {{pm}}        -> chosen package manager (npm|yarn|pnpm|bun)
+///   {{root}}      -> quoted root of the enclosing project (see project.rs), else the file's directory
+///   {{pm}}        -> chosen package manager (npm|yarn|pnpm|bun|deno)
+///   {{pm_run}}    -> pm-specific prefix running a package script (npm run, deno task, ...)
 ///   {{pm_start}}  -> pm-specific start command
 ///   {{pm_test}}   -> pm-specific test command
 ///   {{pm_install}}-> pm-specific install command
+///   {{py}}        -> Python interpreter of the project (uv run python, .venv/bin/python, python3, ...)
+///   {{py_run}}    -> prefix running a Python tool in that environment (uv run, python3 -m, ...)
+///   {{go_root}}   -> quoted root of the Go module (directory of go.mod), else the file's directory
+///   {{go_pkg}}    -> Go package path of the file, relative to the module root (./cmd/tool, or .)
 ///   {{type}}      -> normalized type (e.g., "build", "down"), empty if none
 fn expand_vars(mut s: String, filename: &Path, workdir: &Path, ty: Option<&str>) -> String {
     let file_name = filename.file_name().and_then(|s| s.to_str()).unwrap_or("");
     let file_stem = filename.file_stem().and_then(|s| s.to_str()).unwrap_or("");
-    let dir_disp = workdir.display().to_string();
+    // The command may run from a project root; {{dir}} still names the file's own directory
+    let file_dir = std::fs::canonicalize(filename)
+        .ok()
+        .and_then(|p| p.parent().map(PathBuf::from))
+        .unwrap_or_else(|| workdir.to_path_buf());
+    let dir_disp = file_dir.display().to_string();
+    let root = find_project_root(&file_dir, None).map(|p| p.root).unwrap_or_else(|| file_dir.clone());
 
     // Resolve package manager from workdir
     let pm = pick_package_manager(workdir);
-    let pm_str = match pm { PackageManager::Npm => "npm", PackageManager::Yarn => "yarn", PackageManager::Pnpm => "pnpm", PackageManager::Bun => "bun" };
-    let pm_start = match pm { PackageManager::Npm => "npm start", PackageManager::Yarn => "yarn start", PackageManager::Pnpm => "pnpm start", PackageManager::Bun => "bun run start" };
-    let pm_test  = match pm { PackageManager::Npm => "npm test",  PackageManager::Yarn => "yarn test",  PackageManager::Pnpm => "pnpm test",  PackageManager::Bun => "bun run test" };
-    let pm_install = match pm { PackageManager::Npm => "npm install",  PackageManager::Yarn => "yarn install",  PackageManager::Pnpm => "pnpm install",  PackageManager::Bun => "bun install" };
+    let pm_str = pm.name();
+    let pm_start = pm_script(pm, "start");
+    let pm_test = pm_script(pm, "test");
+    let pm_install = pm_script(pm, "install");
+
+    let py = pick_python_runner(&file_dir);
+    let go = go_package(&file_dir.join(file_name));
 
     let ty_norm = ty.map(normalize_type).unwrap_or_default();
 
@@ -175,10 +253,16 @@ fn expand_vars(mut s: String, filename: &Path, workdir: &Path, ty: Option<&str>)
         ("{{file}}", format!("\"{}\"", file_name)),
         ("{{file_stem}}", format!("\"{}\"", file_stem)),
         ("{{dir}}", format!("\"{}\"", dir_disp)),
+        ("{{root}}", format!("\"{}\"", root.display())),
+        ("{{pm_run}}", pm.run_prefix().to_string()),
+        ("{{pm_start}}", pm_start),
+        ("{{pm_test}}", pm_test),
+        ("{{pm_install}}", pm_install),
         ("{{pm}}", pm_str.to_string()),
-        ("{{pm_start}}", pm_start.to_string()),
-        ("{{pm_test}}", pm_test.to_string()),
-        ("{{pm_install}}", pm_install.to_string()),
+        ("{{py_run}}", py.run_prefix()),
+        ("{{py}}", py.python()),
+        ("{{go_root}}", format!("\"{}\"", go.as_ref().map_or(&file_dir, |g| &g.module_root).display())),
+        ("{{go_pkg}}", go.map_or_else(|| ".".to_string(), |g| g.path)),
         ("{{type}}", ty_norm),
     ];
     for (k, v) in replacements { s = s.replace(k, &v); }
@@ -187,64 +271,495 @@ fn expand_vars(mut s: String, filename: &Path, workdir: &Path, ty: Option<&str>)
 
 #[derive(Debug, Default)]
 struct DefaultsCfg {
-    ext_map: HashMap<String, String>,
+    ext_map: HashMap<String, ExtRule>,
     file_rules: Vec<FileRule>,
+    /// `pack <name> on|off` switches
+    packs: HashMap<String, bool>,
+    /// `alias <type> = <type>, ...` fallback chains, keyed by normalized type
+    aliases: HashMap<String, Vec<String>>,
+    /// `builtin on|off`: whether the built-in project layer is consulted
+    builtin: Option<bool>,
+    /// `modelines on|off`: whether Emacs/Vim compile settings count as recipes (off by default)
+    modelines: Option<bool>,
+    /// Problems found while parsing, with the file and line they come from
+    diagnostics: Vec<(Origin, String)>,
+}
+
+impl DefaultsCfg {
+    /// Layer `higher` on top of `self`: its extension rules replace ours and
+    /// its file rules are tried first.
+    fn overlay(&mut self, higher: DefaultsCfg) {
+        self.ext_map.extend(higher.ext_map);
+        let mut rules = higher.file_rules;
+        rules.append(&mut self.file_rules);
+        self.file_rules = rules;
+        self.packs.extend(higher.packs);
+        self.aliases.extend(higher.aliases);
+        self.builtin = higher.builtin.or(self.builtin);
+        self.modelines = higher.modelines.or(self.modelines);
+        self.diagnostics.extend(higher.diagnostics);
+    }
+
+    /// Splice an included file's rules in at the point of the `include`.
+    fn absorb(&mut self, included: DefaultsCfg) {
+        self.ext_map.extend(included.ext_map);
+        self.file_rules.extend(included.file_rules);
+        self.packs.extend(included.packs);
+        self.aliases.extend(included.aliases);
+        self.builtin = included.builtin.or(self.builtin);
+        self.modelines = included.modelines.or(self.modelines);
+        self.diagnostics.extend(included.diagnostics);
+    }
+}
+
+/// Where a config rule was defined.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Origin { path: PathBuf, line: usize, pack: Option<String> }
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(pack) = &self.pack {
+            write!(f, "pack {}, ", pack)?;
+        }
+        write!(f, "{}:{}", self.path.display(), self.line)
+    }
 }
 
 #[derive(Debug, Clone)]
-struct FileRule { pattern: String, ty: Option<String>, cmd: String }
+struct ExtRule { cmd: String, origin: Origin }
+
+#[derive(Debug, Clone)]
+struct FileRule { pattern: String, ty: Option<String>, cmd: String, origin: Origin }
+
+/// The platform config sections are evaluated against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Platform { os: String, arch: String, host: String }
+
+impl Platform {
+    /// The host platform, unless overridden with `--platform`.
+    fn current() -> Platform {
+        if let Some(p) = PLATFORM_OVERRIDE.get() {
+            return p.clone();
+        }
+        HOST_PLATFORM
+            .get_or_init(|| Platform {
+                os: normalize_os(env::consts::OS).to_string(),
+                arch: env::consts::ARCH.to_string(),
+                host: hostname(),
+            })
+            .clone()
+    }
+
+    /// The current platform with its OS replaced by `name` (macos, linux, unix, windows).
+    fn named(name: &str) -> Option<Platform> {
+        let os = match name.to_ascii_lowercase().as_str() {
+            "macos" | "darwin" | "mac" | "osx" => "macos",
+            "linux" | "unix" => "linux",
+            "windows" | "win" => "windows",
+            _ => return None,
+        };
+        let mut p = Platform::current();
+        p.os = os.to_string();
+        Some(p)
+    }
 
-fn parse_defaults_str(s: &str) -> DefaultsCfg {
+    /// Does a single `key=value[,value...]` section condition hold?
+    fn satisfies(&self, key: &str, values: &str) -> Option<bool> {
+        let any = |f: &dyn Fn(&str) -> bool| values.split(',').map(str::trim).any(f);
+        match key {
+            "os" => Some(any(&|v| {
+                let v = v.to_ascii_lowercase();
+                let v = normalize_os(&v);
+                v == self.os || (v == "unix" && self.os != "windows")
+            })),
+            "arch" => Some(any(&|v| v.eq_ignore_ascii_case(&self.arch))),
+            "host" => Some(any(&|v| glob_match(&v.to_ascii_lowercase(), &self.host.to_ascii_lowercase()))),
+            _ => None,
+        }
+    }
+}
+
+fn normalize_os(os: &str) -> &str {
+    match os {
+        "darwin" | "mac" | "osx" => "macos",
+        "win" => "windows",
+        other => other,
+    }
+}
+
+fn hostname() -> String {
+    for var in ["HOSTNAME", "COMPUTERNAME"] {
+        if let Some(h) = env::var_os(var) {
+            let h = h.to_string_lossy().trim().to_string();
+            if !h.is_empty() {
+                return h;
+            }
+        }
+    }
+    if let Ok(h) = std::fs::read_to_string("/etc/hostname") {
+        let h = h.trim();
+        if !h.is_empty() {
+            return h.to_string();
+        }
+    }
+    Command::new("hostname")
+        .output()
+        .ok()
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Case-sensitive wildcard match supporting `*` (any run) and `?` (one char).
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    let (mut pi, mut ni) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else if let Some((sp, sn)) = star {
+            pi = sp + 1;
+            ni = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// Parse a `[key=value ...]` section header. `[*]` (or `[]`) ends conditional sections.
+/// Returns whether rules below the header apply to `platform`.
+fn section_applies(header: &str, platform: &Platform) -> Result<bool, String> {
+    let mut ok = true;
+    for cond in header.split_whitespace() {
+        if cond == "*" {
+            continue;
+        }
+        let (key, values) = cond
+            .split_once('=')
+            .ok_or_else(|| format!("malformed section condition `{}` (expected key=value)", cond))?;
+        match platform.satisfies(&key.to_ascii_lowercase(), values) {
+            Some(hit) => ok &= hit,
+            None => return Err(format!("unknown section key `{}` (expected os, arch or host)", key)),
+        }
+    }
+    Ok(ok)
+}
+
+/// Parse a config file's text. `src` names the file (and pack) for provenance.
+fn parse_defaults_for(s: &str, platform: &Platform, src: &Origin) -> DefaultsCfg {
+    let key = std::fs::canonicalize(&src.path).unwrap_or_else(|_| src.path.clone());
+    parse_cfg_text(s, platform, src, &mut vec![key])
+}
+
+/// `stack` holds the files currently being included, to detect cycles.
+fn parse_cfg_text(s: &str, platform: &Platform, src: &Origin, stack: &mut Vec<PathBuf>) -> DefaultsCfg {
     let mut cfg = DefaultsCfg::default();
-    let re_ext = Regex::new(r#"^([A-Za-z0-9]+)\s*:\s*(.*)$"#).unwrap();
+    let re_ext = Regex::new(r#"^([A-Za-z0-9]+)(?:\s+-([A-Za-z0-9_-]+))?\s*:\s*(.*)$"#).unwrap();
     let re_file = Regex::new(r#"^file:([^\s:]+)(?:\s+-([A-Za-z0-9_-]+))?\s*:\s*(.*)$"#).unwrap();
-    for line in s.lines() {
+    let re_section = Regex::new(r#"^\[(.*)\]$"#).unwrap();
+    let re_pack = Regex::new(r#"^pack\s+([A-Za-z0-9_.-]+)\s+(on|off)$"#).unwrap();
+    let re_include = Regex::new(r#"^include\s+(.+)$"#).unwrap();
+    let re_builtin = Regex::new(r#"^builtin\s+(on|off)$"#).unwrap();
+    let re_modelines = Regex::new(r#"^modelines\s+(on|off)$"#).unwrap();
+    let re_alias = Regex::new(r#"^alias\s+([A-Za-z0-9_-]+)\s*=\s*(.*)$"#).unwrap();
+    let mut active = true;
+    for (i, line) in s.lines().enumerate() {
         let t = line.trim();
         if t.is_empty() || t.starts_with('#') { continue; }
+        let origin = Origin { line: i + 1, ..src.clone() };
+        if let Some(c) = re_section.captures(t) {
+            active = match section_applies(&c[1], platform) {
+                Ok(a) => a,
+                Err(e) => {
+                    cfg.diagnostics.push((origin, e));
+                    false
+                }
+            };
+            continue;
+        }
+        if !active { continue; }
+        if let Some(c) = re_include.captures(t) {
+            let spec = c[1].trim().trim_matches('"');
+            let base_dir = src.path.parent().unwrap_or(Path::new("."));
+            let targets = match resolve_include(base_dir, spec) {
+                Ok(t) => t,
+                Err(e) => {
+                    cfg.diagnostics.push((origin, e));
+                    continue;
+                }
+            };
+            for target in targets {
+                let key = std::fs::canonicalize(&target).unwrap_or_else(|_| target.clone());
+                if stack.contains(&key) {
+                    let chain: Vec<String> = stack.iter().chain([&key]).map(|p| p.display().to_string()).collect();
+                    cfg.diagnostics.push((origin.clone(), format!("include cycle: {}", chain.join(" -> "))));
+                    continue;
+                }
+                match std::fs::read_to_string(&target) {
+                    Ok(data) => {
+                        let inc_src = Origin { path: target.clone(), line: 0, pack: src.pack.clone() };
+                        stack.push(key);
+                        let inc = parse_cfg_text(&data, platform, &inc_src, stack);
+                        stack.pop();
+                        cfg.absorb(inc);
+                    }
+                    Err(e) => cfg.diagnostics.push((origin.clone(), format!("cannot include {}: {}", target.display(), e))),
+                }
+            }
+            continue;
+        }
+        if let Some(c) = re_pack.captures(t) {
+            cfg.packs.insert(c[1].to_string(), &c[2] == "on");
+            continue;
+        }
+        if let Some(c) = re_builtin.captures(t) {
+            cfg.builtin = Some(&c[1] == "on");
+            continue;
+        }
+        if let Some(c) = re_modelines.captures(t) {
+            cfg.modelines = Some(&c[1] == "on");
+            continue;
+        }
+        if let Some(c) = re_alias.captures(t) {
+            let targets: Vec<String> = c[2]
+                .split([',', ' '])
+                .map(normalize_type)
+                .filter(|t| !t.is_empty())
+                .collect();
+            if targets.is_empty() {
+                cfg.diagnostics.push((origin, format!("alias `{}` has no target types", &c[1])));
+            } else {
+                cfg.aliases.insert(normalize_type(&c[1]), targets);
+            }
+            continue;
+        }
         if let Some(c) = re_file.captures(t) {
             let pat = c.get(1).unwrap().as_str().to_string();
             let ty = c.get(2).map(|m| normalize_type(m.as_str()));
             let cmd = c.get(3).unwrap().as_str().to_string();
-            cfg.file_rules.push(FileRule { pattern: pat, ty, cmd });
+            cfg.file_rules.push(FileRule { pattern: pat, ty, cmd, origin });
             continue;
         }
         if let Some(c) = re_ext.captures(t) {
             let lext = c.get(1).unwrap().as_str().to_ascii_lowercase();
-            let lbuild = c.get(2).unwrap().as_str().to_string();
-            cfg.ext_map.insert(lext, lbuild);
+            let ty = c.get(2).map(|m| normalize_type(m.as_str()));
+            let lbuild = c.get(3).unwrap().as_str().to_string();
+            cfg.ext_map.insert(ext_key(&lext, ty.as_deref()), ExtRule { cmd: lbuild, origin });
+            continue;
+        }
+        cfg.diagnostics.push((origin, format!("unrecognized line `{}`", t)));
+    }
+    cfg
+}
+
+/// Files named by an `include` argument, relative to `base_dir`. `~/` refers to
+/// the home directory; `*` and `?` in the last component match several files,
+/// included in name order. A glob matching nothing is not an error.
+fn resolve_include(base_dir: &Path, spec: &str) -> Result<Vec<PathBuf>, String> {
+    let path = match spec.strip_prefix("~/") {
+        Some(rest) => env::var_os("HOME")
+            .map(|h| PathBuf::from(h).join(rest))
+            .ok_or_else(|| format!("cannot expand `{}`: HOME is not set", spec))?,
+        None => base_dir.join(spec),
+    };
+    let pattern = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    if !pattern.contains(['*', '?']) {
+        return Ok(vec![path]);
+    }
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut hits: Vec<PathBuf> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .filter(|p| p.file_name().is_some_and(|n| glob_match(&pattern, &n.to_string_lossy())))
+        .collect();
+    hits.sort();
+    Ok(hits)
+}
+
+/// Bundled defaults for a platform name as accepted by `--platform`.
+fn bundled_defaults_for(name: &str) -> Option<&'static str> {
+    match Platform::named(name)?.os.as_str() {
+        "macos" => Some(BUNDLED_MACOS),
+        "windows" => Some(BUNDLED_WINDOWS),
+        _ => Some(BUNDLED_UNIX),
+    }
+}
+
+/// Name of the project-local config directory, found by walking up from a file.
+const PROJECT_DIR: &str = ".ruild";
+
+fn read_cfg_file(path: &Path, platform: &Platform, pack: Option<&str>) -> Option<DefaultsCfg> {
+    let data = match std::fs::read_to_string(path) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("failed to open {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    let src = Origin { path: path.to_path_buf(), line: 0, pack: pack.map(str::to_string) };
+    Some(parse_defaults_for(&data, platform, &src))
+}
+
+/// `*.defaults` files of a packs directory as (name, path), sorted by name.
+fn list_packs(dir: &Path) -> Vec<(String, PathBuf)> {
+    let mut packs: Vec<(String, PathBuf)> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "defaults"))
+        .filter_map(|p| Some((p.file_stem()?.to_string_lossy().to_string(), p)))
+        .collect();
+    packs.sort();
+    packs
+}
+
+/// Load every enabled pack of `dir`, later packs (by name) taking precedence.
+fn load_packs(dir: &Path, switches: &HashMap<String, bool>, platform: &Platform) -> DefaultsCfg {
+    let mut cfg = DefaultsCfg::default();
+    for (name, path) in list_packs(dir) {
+        if switches.get(&name) == Some(&false) {
+            continue;
+        }
+        if let Some(pack) = read_cfg_file(&path, platform, Some(&name)) {
+            cfg.overlay(pack);
         }
     }
     cfg
 }
 
-fn load_defaults_cfg() -> Option<DefaultsCfg> {
+/// Nearest `.ruild` directory at or above `start`.
+fn find_project_config_dir(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|d| d.join(PROJECT_DIR))
+        .find(|d| d.is_dir())
+}
+
+/// Load the effective configuration for files under `start`, lowest precedence first:
+/// user packs, user `build.defaults`, project packs, project `.ruild/build.defaults`.
+fn load_defaults_cfg(start: Option<&Path>) -> Option<DefaultsCfg> {
     let p = config_path()?;
     if !p.exists() {
         if let Err(e) = ensure_bootstrap_defaults(&p) {
             eprintln!("could not create default settings at {}: {}", p.display(), e);
             return None;
         }
+        eprintln!("created default settings at {}", p.display());
+    }
+    let platform = Platform::current();
+    let user = read_cfg_file(&p, &platform, None)?;
+
+    let project_dir = start.and_then(find_project_config_dir);
+    let project_file = project_dir.as_ref().map(|d| d.join("build.defaults")).filter(|f| f.is_file());
+    let project = project_file.and_then(|f| read_cfg_file(&f, &platform, None));
+
+    // Project switches override personal ones
+    let mut switches = user.packs.clone();
+    if let Some(pc) = &project {
+        switches.extend(pc.packs.clone());
+    }
+
+    let user_packs = p.parent().map(|d| d.join("packs")).unwrap_or_default();
+    let mut cfg = load_packs(&user_packs, &switches, &platform);
+    cfg.overlay(user);
+    if let Some(dir) = &project_dir {
+        cfg.overlay(load_packs(&dir.join("packs"), &switches, &platform));
+    }
+    if let Some(pc) = project {
+        cfg.overlay(pc);
+    }
+    cfg.packs = switches;
+    if cfg.builtin != Some(false) {
+        cfg.file_rules.retain(|r| {
+            let stale = superseded_by_builtin(r);
+            if stale {
+                let ty = r.ty.as_ref().map(|t| format!(" -{}", t)).unwrap_or_default();
+                report_once(format!(
+                    "{}: skipping `file:{}{}`, a rule from older bundled defaults; the built-in layer handles it (`builtin off` keeps the rule)",
+                    r.origin, r.pattern, ty
+                ));
+            }
+            !stale
+        });
+    }
+    for (origin, msg) in &cfg.diagnostics {
+        report_once(format!("{}: {}", origin, msg));
     }
-    let data = std::fs::read_to_string(&p).ok()?;
-    Some(parse_defaults_str(&data))
+    Some(cfg)
 }
 
-fn match_file_rule(cfg: &DefaultsCfg, name: &str, ty: Option<&str>) -> Option<String> {
-    let lname = name.to_ascii_lowercase();
-    let tnorm = ty.map(normalize_type);
-    for r in &cfg.file_rules {
-        let mut pat = r.pattern.to_ascii_lowercase();
-        let star = pat.ends_with('*');
-        if star { pat.pop(); }
-        let ok = if star { lname.starts_with(&pat) } else { lname == pat };
-        if !ok { continue; }
-        match (&r.ty, &tnorm) {
-            (None, _) => return Some(r.cmd.clone()),
-            (Some(rt), Some(t)) if rt == t => return Some(r.cmd.clone()),
-            _ => continue,
+/// `ext_map` key of an extension rule: `md`, or `md -html` for a typed one.
+fn ext_key(ext: &str, ty: Option<&str>) -> String {
+    match ty {
+        Some(t) if !t.is_empty() => format!("{} -{}", ext, t),
+        _ => ext.to_string(),
+    }
+}
+
+/// `file:` rules older bundled defaults shipped for files the built-in layer
+/// now reads itself (besides the Compose ones, which match what the layer
+/// runs). Configs bootstrapped from those defaults still hold them,
+/// ahead of the built-in layer, so they are passed over while it is on.
+const SUPERSEDED_FILE_RULES: &[(&str, Option<&str>, &str)] = &[
+    ("package.json", None, "{{pm}} run build"),
+    ("package.json", Some("build"), "{{pm}} run build"),
+    ("package.json", Some("start"), "{{pm_start}}"),
+    ("package.json", Some("test"), "{{pm_test}}"),
+    ("package.json", Some("install"), "{{pm_install}}"),
+    ("package.json", Some("lint"), "{{pm}} run lint"),
+    ("package.json", Some("format"), "{{pm}} run format"),
+    ("package.json", Some("fmt"), "{{pm}} run format"),
+    ("package.json", Some("dev"), "{{pm}} run dev"),
+    ("package.json", Some("clean"), "{{pm}} run clean"),
+];
+
+fn superseded_by_builtin(rule: &FileRule) -> bool {
+    let cmd = rule.cmd.split_whitespace().collect::<Vec<_>>().join(" ");
+    // The compose rules ran what the built-in layer runs for the same type
+    if is_compose_file(&rule.pattern.to_ascii_lowercase()) {
+        return compose_cmd(rule.ty.as_deref()) == cmd;
+    }
+    SUPERSEDED_FILE_RULES
+        .iter()
+        .any(|(pat, ty, c)| rule.pattern.eq_ignore_ascii_case(pat) && rule.ty.as_deref() == *ty && cmd == *c)
+}
+
+fn file_rule_matches(rule: &FileRule, lname: &str) -> bool {
+    let mut pat = rule.pattern.to_ascii_lowercase();
+    let star = pat.ends_with('*');
+    if star { pat.pop(); }
+    if star { lname.starts_with(&pat) } else { lname == pat }
+}
+
+/// First `file:` rule for `name` usable for `ty`: a rule declaring that type,
+/// or else an untyped rule.
+fn match_file_rule<'a>(cfg: &'a DefaultsCfg, name: &str, ty: Option<&str>) -> Option<&'a FileRule> {
+    if let Some(t) = ty {
+        if let Some(r) = match_typed_file_rule(cfg, name, t) {
+            return Some(r);
         }
     }
-    None
+    let lname = name.to_ascii_lowercase();
+    cfg.file_rules.iter().find(|r| r.ty.is_none() && file_rule_matches(r, &lname))
+}
+
+fn match_typed_file_rule<'a>(cfg: &'a DefaultsCfg, name: &str, ty: &str) -> Option<&'a FileRule> {
+    let lname = name.to_ascii_lowercase();
+    let tnorm = normalize_type(ty);
+    cfg.file_rules
+        .iter()
+        .find(|r| r.ty.as_deref() == Some(tnorm.as_str()) && file_rule_matches(r, &lname))
 }
 
 /// Returns (base_with_trailing_dot_if_ext, ext_without_dot)
@@ -274,38 +789,177 @@ fn normalize_type(t: &str) -> String {
         .collect()
 }
 
-#[cfg(test)]
+/// Types understood by `compose_cmd`, as listed by `--list`.
+const COMPOSE_TYPES: &[&str] = &["up", "down", "build", "pull", "logs", "ps", "stop", "start", "restart", "recreate", "prune"];
+
 fn compose_cmd(ty: Option<&str>) -> String {
-    let t = ty.map(normalize_type);
-    match t.as_deref() {
-        Some("composedown") | Some("down") | Some("dcdown") => "docker compose down".into(),
-        Some("composebuild") | Some("build") | Some("dcbuild") => "docker compose build".into(),
-        Some("composepull") | Some("pull") | Some("dcpull") => "docker compose pull".into(),
-        Some("composelogs") | Some("logs") | Some("dclogs") => "docker compose logs -f".into(),
-        Some("composeps") | Some("ps") | Some("dcps") => "docker compose ps".into(),
-        Some("composestop") | Some("stop") | Some("dcstop") => "docker compose stop".into(),
-        Some("composestart") | Some("start") | Some("dcstart") => "docker compose start".into(),
-        Some("composerestart") | Some("restart") | Some("dcrestart") => "docker compose restart".into(),
-        Some("composerecreate") | Some("recreate") => "docker compose up -d --force-recreate".into(),
-        Some("composeprune") | Some("prune") => "docker compose down --volumes --remove-orphans".into(),
-        _ => "docker compose up -d".into(),
+    ty.and_then(compose_action).unwrap_or("docker compose up -d").into()
+}
+
+/// Compose command for a known action type, `None` for anything else.
+fn compose_action(ty: &str) -> Option<&'static str> {
+    let t = normalize_type(ty);
+    Some(match t.as_str() {
+        "composeup" | "up" | "dcup" => "docker compose up -d",
+        "composedown" | "down" | "dcdown" => "docker compose down",
+        "composebuild" | "build" | "dcbuild" => "docker compose build",
+        "composepull" | "pull" | "dcpull" => "docker compose pull",
+        "composelogs" | "logs" | "dclogs" => "docker compose logs -f",
+        "composeps" | "ps" | "dcps" => "docker compose ps",
+        "composestop" | "stop" | "dcstop" => "docker compose stop",
+        "composestart" | "start" | "dcstart" => "docker compose start",
+        "composerestart" | "restart" | "dcrestart" => "docker compose restart",
+        "composerecreate" | "recreate" => "docker compose up -d --force-recreate",
+        "composeprune" | "prune" => "docker compose down --volumes --remove-orphans",
+        _ => return None,
+    })
+}
+
+fn is_compose_file(lname: &str) -> bool {
+    matches!(lname, "docker-compose.yml" | "docker-compose.yaml" | "compose.yml" | "compose.yaml")
+}
+
+/// Services and profiles declared by a compose file.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ComposeInfo {
+    services: Vec<String>,
+    profiles: Vec<String>,
+}
+
+/// Read `services:` and the services' `profiles` from compose YAML. This is a
+/// line-based reader covering the block and flow styles compose files use.
+fn parse_compose(text: &str) -> ComposeInfo {
+    let mut info = ComposeInfo::default();
+    let indent_of = |l: &str| l.len() - l.trim_start().len();
+    let unquote = |s: &str| s.trim().trim_matches(|c| c == '"' || c == '\'').to_string();
+    let mut in_services = false;
+    let mut service_indent: Option<usize> = None;
+    let mut profiles_indent: Option<usize> = None;
+    for line in text.lines() {
+        let t = line.trim();
+        if t.is_empty() || t.starts_with('#') {
+            continue;
+        }
+        let indent = indent_of(line);
+        if indent == 0 {
+            in_services = t == "services:";
+            service_indent = None;
+            profiles_indent = None;
+            continue;
+        }
+        if !in_services {
+            continue;
+        }
+        if let Some(pi) = profiles_indent {
+            if indent > pi && t.starts_with('-') {
+                info.profiles.push(unquote(&t[1..]));
+                continue;
+            }
+            profiles_indent = None;
+        }
+        let si = *service_indent.get_or_insert(indent);
+        if indent == si {
+            if let Some(name) = t.strip_suffix(':').or_else(|| t.split_once(':').map(|(k, _)| k)) {
+                info.services.push(unquote(name));
+            }
+        } else if let Some(rest) = t.strip_prefix("profiles:") {
+            let rest = rest.trim();
+            if rest.is_empty() {
+                profiles_indent = Some(indent);
+            } else {
+                let list = rest.trim_start_matches('[').trim_end_matches(']');
+                info.profiles.extend(list.split(',').map(unquote).filter(|p| !p.is_empty()));
+            }
+        }
+    }
+    info.profiles.sort();
+    info.profiles.dedup();
+    info
+}
+
+/// `-<action>:<target>` on a compose file: the action limited to one service,
+/// or run with `--profile` when the target names a profile.
+fn compose_target_cmd(action: &str, target: &str, info: &ComposeInfo) -> Option<String> {
+    let cmd = compose_action(action)?;
+    if info.services.iter().any(|s| s == target) {
+        if normalize_type(action).ends_with("prune") {
+            return None;
+        }
+        return Some(format!("{} {}", cmd, target));
+    }
+    if info.profiles.iter().any(|p| p == target) {
+        let rest = cmd.trim_start_matches("docker compose ");
+        return Some(format!("docker compose --profile {} {}", target, rest));
     }
+    None
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
-enum PackageManager { Npm, Yarn, Pnpm, Bun }
+enum PackageManager { Npm, Yarn, Pnpm, Bun, Deno }
+
+impl PackageManager {
+    fn name(self) -> &'static str {
+        match self {
+            PackageManager::Npm => "npm",
+            PackageManager::Yarn => "yarn",
+            PackageManager::Pnpm => "pnpm",
+            PackageManager::Bun => "bun",
+            PackageManager::Deno => "deno",
+        }
+    }
 
+    /// Prefix that runs a named script (`npm run`, `deno task`, ...).
+    fn run_prefix(self) -> &'static str {
+        match self {
+            PackageManager::Npm => "npm run",
+            PackageManager::Yarn => "yarn run",
+            PackageManager::Pnpm => "pnpm run",
+            PackageManager::Bun => "bun run",
+            PackageManager::Deno => "deno task",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<PackageManager> {
+        match name {
+            "npm" => Some(PackageManager::Npm),
+            "yarn" => Some(PackageManager::Yarn),
+            "pnpm" => Some(PackageManager::Pnpm),
+            "bun" => Some(PackageManager::Bun),
+            "deno" => Some(PackageManager::Deno),
+            _ => None,
+        }
+    }
+}
+
+/// Package manager for a JavaScript package in `dir`. Walks up to the
+/// workspace root: at each level the `packageManager` field of package.json
+/// wins, then lockfiles, then a Deno config. The walk stops at a repository
+/// root (`.git`) or a pnpm workspace root.
 fn pick_package_manager(dir: &Path) -> PackageManager {
-    // Heuristics based on lockfiles
-    let has = |name: &str| dir.join(name).exists();
-    if has("pnpm-lock.yaml") { return PackageManager::Pnpm; }
-    if has("yarn.lock") { return PackageManager::Yarn; }
-    if has("bun.lockb") { return PackageManager::Bun; }
-    // package-lock.json implies npm
+    for d in dir.ancestors() {
+        if let Some(pm) = package_manager_field(&d.join("package.json")) {
+            return pm;
+        }
+        // Heuristics based on lockfiles
+        let has = |name: &str| d.join(name).exists();
+        if has("pnpm-lock.yaml") { return PackageManager::Pnpm; }
+        if has("yarn.lock") { return PackageManager::Yarn; }
+        if has("bun.lockb") || has("bun.lock") { return PackageManager::Bun; }
+        if has("package-lock.json") { return PackageManager::Npm; }
+        if has("deno.json") || has("deno.jsonc") || has("deno.lock") { return PackageManager::Deno; }
+        if has(".git") || has("pnpm-workspace.yaml") { break; }
+    }
     PackageManager::Npm
 }
 
-#[cfg(test)]
+/// The manager named by package.json's `packageManager` field (`"pnpm@9.1.0"`).
+fn package_manager_field(package_json: &Path) -> Option<PackageManager> {
+    let data = std::fs::read_to_string(package_json).ok()?;
+    let json: serde_json::Value = serde_json::from_str(&data).ok()?;
+    let field = json.get("packageManager")?.as_str()?;
+    PackageManager::from_name(field.split('@').next()?.trim())
+}
+
 fn pm_script(pm: PackageManager, script: &str) -> String {
     let s = script.to_ascii_lowercase();
     match pm {
@@ -313,24 +967,31 @@ fn pm_script(pm: PackageManager, script: &str) -> String {
             "install" => "npm install".into(),
             "start" => "npm start".into(),
             "test" => "npm test".into(),
-            _ => format!("npm run {}", s),
+            _ => format!("npm run {}", script),
         },
         PackageManager::Yarn => match s.as_str() {
             "install" => "yarn install".into(),
-            _ => format!("yarn {}", s),
+            _ => format!("yarn run {}", script),
         },
         PackageManager::Pnpm => match s.as_str() {
             "install" => "pnpm install".into(),
-            _ => format!("pnpm {}", s),
+            _ => format!("pnpm run {}", script),
         },
         PackageManager::Bun => match s.as_str() {
             "install" => "bun install".into(),
-            _ => format!("bun run {}", s),
+            _ => format!("bun run {}", script),
+        },
+        PackageManager::Deno => match s.as_str() {
+            "install" => "deno install".into(),
+            "test" => "deno test".into(),
+            _ => format!("deno task {}", script),
         },
     }
 }
 
-#[cfg(test)]
+/// Built-in project layer: commands for well-known project files (mdbook,
+/// mkdocs, sphinx, doxygen, compose, package.json). Consulted after the user's
+/// `file:` rules unless the config says `builtin off`.
 fn project_command_for_file(type_expected: Option<&str>, path: &Path) -> Option<String> {
     let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
     if name == "book.toml" {
@@ -346,15 +1007,22 @@ fn project_command_for_file(type_expected: Option<&str>, path: &Path) -> Option<
         let fname = path.file_name()?.to_string_lossy().to_string();
         return Some(format!("doxygen {}", fname));
     }
-    if name == "docker-compose.yml" || name == "docker-compose.yaml" || name == "compose.yml" || name == "compose.yaml" {
+    if is_compose_file(&name) {
+        if let Some((action, target)) = type_expected.and_then(|t| t.split_once(':')) {
+            let info = parse_compose(&std::fs::read_to_string(path).ok()?);
+            return compose_target_cmd(action, target, &info);
+        }
         return Some(compose_cmd(type_expected));
     }
+    if makefile::is_makefile(&name) {
+        return makefile::make_command(path, type_expected);
+    }
     if name == "package.json" {
         let dir = path.parent().unwrap_or(Path::new("."));
         let pm = pick_package_manager(dir);
         // Default to build when no type is specified
         let tnorm = type_expected.map(normalize_type);
-        let script = match tnorm.as_deref() {
+        let legacy = match tnorm.as_deref() {
             Some("start") | Some("npmstart") => "start",
             Some("test") | Some("npmtest") => "test",
             Some("build") | Some("npmbuild") => "build",
@@ -363,13 +1031,68 @@ fn project_command_for_file(type_expected: Option<&str>, path: &Path) -> Option<
             Some("dev") | Some("npmdev") => "dev",
             Some("clean") | Some("npmclean") => "clean",
             Some("install") | Some("npminstall") => "install",
-            _ => "build",
+            Some(_) => "",
+            None => "build",
+        };
+        if legacy == "install" {
+            return Some(pm_script(pm, "install"));
+        }
+        // With readable scripts, only scripts that exist are offered
+        let Some(scripts) = package_scripts(path) else {
+            return Some(pm_script(pm, if legacy.is_empty() { "build" } else { legacy }));
         };
+        let want = tnorm.unwrap_or_else(|| "build".into());
+        let script = scripts
+            .iter()
+            .find(|s| normalize_type(s) == want)
+            .or_else(|| scripts.iter().find(|s| !legacy.is_empty() && normalize_type(s) == legacy))?;
         return Some(pm_script(pm, script));
     }
     None
 }
 
+/// Names of the `scripts` of a package.json, sorted; `None` if it can't be read.
+fn package_scripts(path: &Path) -> Option<Vec<String>> {
+    let data = std::fs::read_to_string(path).ok()?;
+    let json: serde_json::Value = serde_json::from_str(&data).ok()?;
+    Some(match json.get("scripts").and_then(|s| s.as_object()) {
+        Some(scripts) => scripts.keys().cloned().collect(),
+        None => Vec::new(),
+    })
+}
+
+/// Types the built-in project layer offers for `path` (whose directory is `workdir`).
+fn builtin_types(path: &Path, workdir: &Path) -> Vec<String> {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+    if makefile::is_makefile(&name) {
+        let text = std::fs::read_to_string(path).unwrap_or_default();
+        return makefile::make_targets(&text).into_iter().map(|t| t.name).collect();
+    }
+    if name == "package.json" {
+        let mut types = package_scripts(path).unwrap_or_default();
+        types.push("install".into());
+        return types;
+    }
+    if is_compose_file(&name) {
+        let mut types: Vec<String> = COMPOSE_TYPES.iter().map(|t| t.to_string()).collect();
+        // Per-service types are listed for the everyday actions only
+        let info = parse_compose(&std::fs::read_to_string(path).unwrap_or_default());
+        for target in info.services.iter().chain(&info.profiles) {
+            types.extend(["up", "logs", "restart"].iter().map(|a| format!("{}:{}", a, target)));
+        }
+        return types;
+    }
+    let ext = base_and_ext(path).1;
+    let mut types: Vec<String> = match find_project_root(workdir, Some(&ext)) {
+        Some(p) => project::project_types(&p).iter().map(|t| t.to_string()).collect(),
+        None => Vec::new(),
+    };
+    if compile_db_command(path, Some("syntax")).is_some() {
+        types.extend(["build".to_string(), "syntax".to_string()]);
+    }
+    types
+}
+
 fn append_command_segment(cmd: &mut String, fragment: &str) {
     let part = fragment.trim();
     if part.is_empty() {
@@ -385,7 +1108,7 @@ fn collect_html_command<I>(mut cmd: String, lines: &mut I) -> String
 where
     I: Iterator<Item = std::io::Result<String>>,
 {
-    while let Some(line_res) = lines.next() {
+    for line_res in lines.by_ref() {
         let line = match line_res {
             Ok(line) => line,
             Err(_) => break,
@@ -404,73 +1127,622 @@ where
     cmd
 }
 
-fn build_file(type_expected: Option<&str>, filename: &Path) -> bool {
-    let fh = match File::open(filename) {
-        Ok(f) => f,
-        Err(_) => {
-            println!("can not read {}", filename.display());
-            return false;
+/// Where a resolved recipe came from, for `--explain`.
+#[derive(Debug, Clone)]
+enum Source {
+    /// `@build` directive on the given (1-based) line of the file itself
+    Inline { line: usize },
+    /// Directive on the given line of a sidecar file (`<file>.build` or `.build`)
+    Sidecar { path: PathBuf, line: usize },
+    /// `% !TEX program` magic comment on the given line
+    TexProgram { line: usize },
+    /// Emacs or Vim compile setting on the given line (`modelines on`)
+    Editor { kind: &'static str, line: usize },
+    /// `@build-target` or `% !TEX root` on the given line, naming the file whose
+    /// recipe is used; `via` says where that file's recipe came from
+    Target { path: PathBuf, line: usize, via: Box<Source> },
+    /// `file:` rule of the configuration
+    FileRule(Origin),
+    /// Built-in project layer, keyed by the file name it recognized
+    Builtin { name: String },
+    /// Compiler invocation recorded for the file in a compilation database
+    CompileDb { db: PathBuf },
+    /// Whole-project build of the project identified by a marker file above the file
+    Project { marker: PathBuf },
+    /// Extension rule of the configuration
+    Extension { ext: String, origin: Origin },
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Inline { line } => write!(f, "inline @build (line {})", line),
+            Source::Sidecar { path, line } => write!(f, "sidecar {}:{}", path.display(), line),
+            Source::TexProgram { line } => write!(f, "% !TEX program (line {})", line),
+            Source::Editor { kind, line } => write!(f, "{} (line {}, enabled by `modelines on`)", kind, line),
+            Source::Target { path, line, via } => write!(f, "target {} (line {}), then {}", path.display(), line, via),
+            Source::FileRule(origin) => write!(f, "file rule ({})", origin),
+            Source::Builtin { name } => write!(f, "built-in project rule for {} (disable with `builtin off`)", name),
+            Source::CompileDb { db } => write!(f, "recorded command in {} (disable with `builtin off`)", db.display()),
+            Source::Project { marker } => write!(f, "project build for {} (disable with `builtin off`)", marker.display()),
+            Source::Extension { ext, origin } => write!(f, "extension rule `{}` ({})", ext, origin),
         }
-    };
+    }
+}
 
-    let (base, ext) = base_and_ext(filename);
+impl Source {
+    /// Where the recipe itself came from, through any `@build-target` hops.
+    fn recipe(&self) -> &Source {
+        match self {
+            Source::Target { via, .. } => via.recipe(),
+            other => other,
+        }
+    }
+}
 
-    // Ensure relative paths in build commands resolve from the file's directory
-    let workdir = match std::fs::canonicalize(filename) {
-        Ok(abs) => abs.parent().map(PathBuf::from).unwrap_or_else(|| PathBuf::from(".")),
-        Err(_) => filename.parent().map(PathBuf::from).unwrap_or_else(|| PathBuf::from(".")),
+/// A recipe chosen for a file, ready to expand and run.
+#[derive(Debug, Clone)]
+struct Resolution {
+    tpl: String,
+    base: String,
+    workdir: PathBuf,
+    /// File the recipe belongs to: the requested one, or the target it delegates to
+    file: PathBuf,
+    source: Source,
+}
+
+impl Resolution {
+    /// The shell command line to run. Recorded compiler invocations are
+    /// complete commands whose `%` and braces belong to the compiler flags,
+    /// so they run as written. Editor settings are shell commands too: their
+    /// `%` is literal (`date +%Y`), and only the `{{file}}`/`{{file_stem}}`
+    /// that Vim's filename modifiers became are filled in.
+    fn command(&self, ty: Option<&str>) -> String {
+        match self.source.recipe() {
+            Source::CompileDb { .. } => self.tpl.clone(),
+            Source::Editor { .. } => expand_vars(self.tpl.clone(), &self.file, &self.workdir, ty),
+            _ => expand_command(&self.tpl, &self.base, &self.workdir, &self.file, ty),
+        }
+    }
+}
+
+/// An inline `@build` directive found in a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Directive {
+    /// Declared type, empty for a plain `@build`
+    ty: String,
+    tpl: String,
+    /// 1-based line the directive starts on
+    line: usize,
+    /// Sidecar file the directive was read from, `None` for the file itself
+    sidecar: Option<PathBuf>,
+}
+
+impl Directive {
+    fn source(&self) -> Source {
+        match &self.sidecar {
+            Some(path) => Source::Sidecar { path: path.clone(), line: self.line },
+            None => Source::Inline { line: self.line },
+        }
+    }
+}
+
+/// What a file says about how to build it.
+#[derive(Debug, Default)]
+struct FileScan {
+    directives: Vec<Directive>,
+    /// First `@build-target <path>` or `% !TEX root = <path>`, with its line
+    target: Option<(String, usize)>,
+    /// First `% !TEX program = <name>`, with its line
+    tex_program: Option<(String, usize)>,
+}
+
+/// Collect every `@build` directive of a file, in order.
+fn scan_directives(filename: &Path) -> std::io::Result<Vec<Directive>> {
+    scan_file(filename).map(|scan| scan.directives)
+}
+
+/// Collect the directives and magic comments of a file, followed by those of
+/// its sidecar files.
+fn scan_file(filename: &Path) -> std::io::Result<FileScan> {
+    scan_contents(filename).map(|scan| with_sidecars(filename, scan))
+}
+
+/// `scan` followed by the directives of the sidecar files of `filename`.
+fn with_sidecars(filename: &Path, mut scan: FileScan) -> FileScan {
+    let sidecar = scan_sidecars(filename);
+    scan.directives.extend(sidecar.directives);
+    scan.target = scan.target.or(sidecar.target);
+    scan
+}
+
+/// Collect the directives and magic comments inside a file.
+fn scan_contents(filename: &Path) -> std::io::Result<FileScan> {
+    if base_and_ext(filename).1.eq_ignore_ascii_case("ipynb") {
+        return std::fs::read_to_string(filename).map(|text| notebook::scan_notebook(&text));
+    }
+    if frontmatter::is_document(&base_and_ext(filename).1) {
+        let text = String::from_utf8_lossy(&std::fs::read(filename)?).into_owned();
+        return Ok(scan_text(filename, &text));
+    }
+    let fh = File::open(filename)?;
+    Ok(scan_lines(BufReader::new(fh).lines()))
+}
+
+/// Collect the directives and magic comments of `text`, the contents of
+/// `filename` (an editor buffer, for the language server).
+fn scan_text(filename: &Path, text: &str) -> FileScan {
+    let ext = base_and_ext(filename).1;
+    if ext.eq_ignore_ascii_case("ipynb") {
+        return notebook::scan_notebook(text);
+    }
+    let mut scan = scan_lines(text.lines().map(|l| Ok(l.to_string())));
+    if frontmatter::is_document(&ext) {
+        let mut front = frontmatter::directives(text);
+        if !front.is_empty() {
+            front.append(&mut scan.directives);
+            front.sort_by_key(|d| d.line);
+            scan.directives = front;
+        }
+    }
+    scan
+}
+
+/// Directives kept next to a file that cannot hold comments: the lines of
+/// `<name>.build` (`@build-png ...`, with or without a comment marker), then
+/// the `<pattern> [-type]: <cmd>` rules of the directory's `.build` file that
+/// match the file name.
+fn scan_sidecars(filename: &Path) -> FileScan {
+    let mut scan = FileScan::default();
+    let Some(name) = filename.file_name().map(|n| n.to_string_lossy().to_string()) else {
+        return scan;
     };
+    let dir = filename.parent().unwrap_or(Path::new(""));
+
+    let own = dir.join(format!("{}.build", name));
+    if let Ok(text) = std::fs::read_to_string(&own) {
+        let lines = text.lines().map(|l| {
+            let t = l.trim_start();
+            Ok(if t.starts_with("@build") { format!("# {}", t) } else { l.to_string() })
+        });
+        let own_scan = scan_lines(lines);
+        scan.directives.extend(own_scan.directives.into_iter().map(|d| Directive { sidecar: Some(own.clone()), ..d }));
+        scan.target = own_scan.target;
+    }
 
-    let mut lines = BufReader::new(fh).lines();
+    let shared = dir.join(".build");
+    if let Ok(text) = std::fs::read_to_string(&shared) {
+        let re_rule = Regex::new(r#"^([^\s:]+)(?:\s+-([A-Za-z0-9_-]+))?\s*:\s*(.*)$"#).unwrap();
+        for (i, line) in text.lines().enumerate() {
+            let t = line.trim();
+            if t.is_empty() || t.starts_with('#') {
+                continue;
+            }
+            let Some(c) = re_rule.captures(t) else { continue };
+            if glob_match(&c[1], &name) && !c[3].trim().is_empty() {
+                let ty = c.get(2).map_or("", |m| m.as_str()).to_string();
+                scan.directives.push(Directive { ty, tpl: c[3].trim().to_string(), line: i + 1, sidecar: Some(shared.clone()) });
+            }
+        }
+    }
+    scan
+}
+
+/// Collect the directives and magic comments of a sequence of lines.
+fn scan_lines<I>(mut lines: I) -> FileScan
+where
+    I: Iterator<Item = std::io::Result<String>>,
+{
+    let mut lineno = 0;
+    let mut scan = FileScan::default();
 
     // Scan the whole file (the Lua had a TODO to limit to 100 lines; we keep the original behavior)
     while let Some(line_res) = lines.next() {
+        lineno += 1;
         let line = match line_res {
             Ok(line) => line,
             Err(_) => continue,
         };
+        if let Some((key, value)) = tex_magic(&line) {
+            let slot = if key == "root" { &mut scan.target } else { &mut scan.tex_program };
+            if slot.is_none() && !value.is_empty() {
+                *slot = Some((value, lineno));
+            }
+            continue;
+        }
         if let Some((ty, mut build_tpl)) = detect(&line) {
+            if ty.eq_ignore_ascii_case("target") {
+                if scan.target.is_none() && !build_tpl.trim().is_empty() {
+                    scan.target = Some((build_tpl.trim().to_string(), lineno));
+                }
+                continue;
+            }
+            let start = lineno;
             let is_multiline_html = {
                 let trimmed = line.trim_start();
                 trimmed.starts_with("<!--") && !trimmed.contains("-->")
             };
             if is_multiline_html {
-                build_tpl = collect_html_command(build_tpl, &mut lines);
+                let mut consumed = 0;
+                build_tpl = collect_html_command(build_tpl, &mut lines.by_ref().inspect(|_| consumed += 1));
+                lineno += consumed;
             }
-            let ok_type = match type_expected {
-                None => true,
-                Some(want) => !ty.is_empty() && ty == want,
-            };
-            if ok_type && !build_tpl.is_empty() {
-                return run_command(&build_tpl, &base, &workdir, filename, type_expected);
+            if !build_tpl.is_empty() {
+                scan.directives.push(Directive { ty, tpl: build_tpl, line: start, sidecar: None });
+            }
+        }
+    }
+    scan
+}
+
+/// First directive usable for `want`: any directive when no type is requested,
+/// otherwise one declaring that type.
+fn pick_directive<'a>(directives: &'a [Directive], want: Option<&str>) -> Option<&'a Directive> {
+    directives.iter().find(|d| match want {
+        None => true,
+        Some(w) => !d.ty.is_empty() && normalize_type(&d.ty) == normalize_type(w),
+    })
+}
+
+/// Types to try for a requested type: itself, then its aliases in declaration
+/// order, following aliases of aliases.
+fn type_chain(cfg: &DefaultsCfg, want: &str) -> Vec<String> {
+    let mut chain = vec![normalize_type(want)];
+    let mut i = 0;
+    while i < chain.len() {
+        if let Some(targets) = cfg.aliases.get(&chain[i]) {
+            for t in targets {
+                if !chain.contains(t) {
+                    chain.push(t.clone());
+                }
+            }
+        }
+        i += 1;
+    }
+    chain
+}
+
+/// Every type that could be requested for this file: inline types, typed
+/// `file:` rules, built-in project types, typed extension rules (`md -html`) and
+/// aliases.
+fn known_types(cfg: &DefaultsCfg, directives: &[Directive], filename: &Path, workdir: &Path) -> Vec<String> {
+    let mut types: Vec<String> = directives
+        .iter()
+        .filter(|d| !d.ty.is_empty())
+        .map(|d| normalize_type(&d.ty))
+        .collect();
+    let name = filename.file_name().and_then(|s| s.to_str()).unwrap_or("");
+    let lname = name.to_ascii_lowercase();
+    types.extend(
+        cfg.file_rules
+            .iter()
+            .filter(|r| file_rule_matches(r, &lname))
+            .filter_map(|r| r.ty.clone()),
+    );
+    if cfg.builtin != Some(false) {
+        types.extend(builtin_types(&workdir.join(name), workdir));
+    }
+    let lext = base_and_ext(filename).1.to_ascii_lowercase();
+    if !lext.is_empty() {
+        let prefix = format!("{} -", lext);
+        types.extend(cfg.ext_map.keys().filter_map(|k| k.strip_prefix(&prefix)).map(str::to_string));
+    }
+    types.extend(cfg.aliases.keys().cloned());
+    types.sort();
+    types.dedup();
+    types
+}
+
+/// Edit distance counting insertions, deletions, substitutions and adjacent
+/// transpositions (so `htlm` is one edit from `html`).
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j - 1] + cost).min(d[i - 1][j] + 1).min(d[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// The known type closest to `want`, if any is plausibly a typo of it.
+fn suggest_type<'a>(want: &str, known: &'a [String]) -> Option<&'a str> {
+    let want = normalize_type(want);
+    known
+        .iter()
+        .map(|k| (edit_distance(&want, &normalize_type(k)), k))
+        .filter(|(d, k)| *d <= (k.len().max(want.len()) / 3).max(1) || normalize_type(k).starts_with(&want))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, k)| k.as_str())
+}
+
+/// Longest chain of `@build-target` / `% !TEX root` hops followed.
+const MAX_TARGET_HOPS: usize = 8;
+
+/// Find the recipe for `filename`: inline directives first, then a target the
+/// file delegates to, `file:` rules, the built-in project layer, then extension
+/// defaults. A requested type also tries its aliases at each step.
+fn resolve(type_expected: Option<&str>, filename: &Path) -> Option<Resolution> {
+    resolve_hops(type_expected, filename, 0)
+}
+
+/// Like `resolve`, for `filename` holding `text`: an editor buffer, which may
+/// differ from the file on disk or not be saved at all.
+fn resolve_text(type_expected: Option<&str>, filename: &Path, text: &str) -> Option<Resolution> {
+    let scan = with_sidecars(filename, scan_text(filename, text));
+    resolve_scan(type_expected, filename, scan, Some(text), 0)
+}
+
+fn resolve_hops(type_expected: Option<&str>, filename: &Path, hops: usize) -> Option<Resolution> {
+    match scan_file(filename) {
+        Ok(scan) => resolve_scan(type_expected, filename, scan, None, hops),
+        Err(_) => {
+            eprintln!("can not read {}", filename.display());
+            None
+        }
+    }
+}
+
+/// Resolve `filename` from its scan; `text` is its contents when they are
+/// already at hand, otherwise they are read when needed.
+fn resolve_scan(type_expected: Option<&str>, filename: &Path, scan: FileScan, text: Option<&str>, hops: usize) -> Option<Resolution> {
+    let FileScan { directives, target, tex_program } = scan;
+
+    let (base, ext) = base_and_ext(filename);
+    let workdir = file_workdir(filename);
+    let found = |tpl: String, source: Source| {
+        Some(Resolution { tpl, base: base.clone(), workdir: workdir.clone(), file: filename.to_path_buf(), source })
+    };
+
+    if let Some(d) = pick_directive(&directives, type_expected) {
+        return found(d.tpl.clone(), d.source());
+    }
+
+    let cfg = load_defaults_cfg(Some(&workdir))?;
+    let chain = type_expected.map(|t| type_chain(&cfg, t)).unwrap_or_default();
+
+    for t in chain.iter().skip(1) {
+        if let Some(d) = pick_directive(&directives, Some(t)) {
+            return found(d.tpl.clone(), d.source());
+        }
+    }
+
+    // A chapter delegates to its root document, built from the root's directory
+    if let Some((path, line)) = target {
+        if hops >= MAX_TARGET_HOPS {
+            eprintln!("{}: too many @build-target hops", filename.display());
+            return None;
+        }
+        let path = workdir.join(path);
+        let r = resolve_hops(type_expected, &path, hops + 1)?;
+        return Some(Resolution { source: Source::Target { path, line, via: Box::new(r.source.clone()) }, ..r });
+    }
+
+    // Settings without a type only answer a plain build
+    let plain = chain.first().is_none_or(|t| t == "build");
+    if plain {
+        if let Some((program, line)) = tex_program {
+            return found(tex_program_command(&program), Source::TexProgram { line });
+        }
+        if cfg.modelines == Some(true) {
+            let text = text.map_or_else(|| std::fs::read_to_string(filename).unwrap_or_default(), str::to_string);
+            if let Some(e) = modeline::editor_recipe(&text) {
+                return found(e.cmd, Source::Editor { kind: e.kind, line: e.line });
             }
         }
     }
 
     // Project-aware fallbacks from config defaults
-    if let Some(cfg) = load_defaults_cfg() {
-        if let Some(tpl) = match_file_rule(&cfg, filename.file_name().and_then(|s| s.to_str()).unwrap_or(""), type_expected) {
-            return run_command(&tpl, &base, &workdir, filename, type_expected);
+    let name = filename.file_name().and_then(|s| s.to_str()).unwrap_or("");
+    let rule = chain
+        .iter()
+        .find_map(|t| match_typed_file_rule(&cfg, name, t))
+        .or_else(|| if plain { match_file_rule(&cfg, name, None) } else { None });
+    if let Some(rule) = rule {
+        return found(rule.cmd.clone(), Source::FileRule(rule.origin.clone()));
+    }
+
+    if cfg.builtin != Some(false) {
+        if let Some(cmd) = project_command_for_file(type_expected, filename) {
+            return found(cmd, Source::Builtin { name: name.to_string() });
+        }
+        let abs = workdir.join(name);
+        // A C/C++ file listed in compile_commands.json compiles with its recorded flags
+        if let Some((cmd, dir, db)) = compile_db_command(&abs, type_expected) {
+            return Some(Resolution { tpl: cmd, base, workdir: dir, file: filename.to_path_buf(), source: Source::CompileDb { db } });
+        }
+        // A source file inside a project builds the whole project from its root
+        if let Some(p) = find_project_root(&workdir, Some(&ext)) {
+            if let Some((cmd, dir)) = project_command(&p, &abs, type_expected) {
+                return Some(Resolution { tpl: cmd, base, workdir: dir, file: filename.to_path_buf(), source: Source::Project { marker: p.marker } });
+            }
         }
     }
 
     // Try defaults if nothing was found inline or via project detection
-    if let Some(default_tpl) = read_defaults(&ext) {
-        return run_command(&default_tpl, &base, &workdir, filename, type_expected);
+    let untyped = plain || declares_type(&cfg, &chain, &directives, filename, &workdir);
+    let (tpl, source) = extension_rule(&cfg, &chain, &ext, untyped)?;
+    found(tpl, source)
+}
+
+/// Whether the file knows a type of `chain`, so that a missing typed
+/// extension rule may fall back to the plain one. An unknown type (`-htlm`)
+/// finds nothing and gets a suggestion instead.
+fn declares_type(cfg: &DefaultsCfg, chain: &[String], directives: &[Directive], filename: &Path, workdir: &Path) -> bool {
+    let known = known_types(cfg, directives, filename, workdir);
+    chain.iter().any(|t| known.contains(t))
+}
+
+/// The extension default for `ext`; a type first looks for a typed extension
+/// rule such as `md -html`, then the plain rule if `untyped` is set.
+fn extension_rule(cfg: &DefaultsCfg, chain: &[String], ext: &str, untyped: bool) -> Option<(String, Source)> {
+    let lext = ext.to_ascii_lowercase();
+    let keys = chain.iter().map(|t| ext_key(&lext, Some(t))).filter(|_| !lext.is_empty());
+    for key in keys.chain(untyped.then(|| lext.clone())) {
+        if let Some(rule) = cfg.ext_map.get(&key) {
+            return Some((rule.cmd.clone(), Source::Extension { ext: key, origin: rule.origin.clone() }));
+        }
+    }
+    None
+}
+
+/// The recipe the extension defaults give `filename` on its own, ignoring
+/// its directives and any project around it.
+fn resolve_extension(type_expected: Option<&str>, filename: &Path) -> Option<Resolution> {
+    let (base, ext) = base_and_ext(filename);
+    let workdir = file_workdir(filename);
+    let cfg = load_defaults_cfg(Some(&workdir))?;
+    let chain = type_expected.map(|t| type_chain(&cfg, t)).unwrap_or_default();
+    let plain = chain.first().is_none_or(|t| t == "build");
+    let untyped = plain || declares_type(&cfg, &chain, &scan_directives(filename).unwrap_or_default(), filename, &workdir);
+    let (tpl, source) = extension_rule(&cfg, &chain, &ext, untyped)?;
+    Some(Resolution { tpl, base, workdir, file: filename.to_path_buf(), source })
+}
+
+/// Ensure relative paths in build commands resolve from the file's directory
+fn file_workdir(filename: &Path) -> PathBuf {
+    match std::fs::canonicalize(filename) {
+        Ok(abs) => abs.parent().map(PathBuf::from).unwrap_or_else(|| PathBuf::from(".")),
+        Err(_) => filename.parent().map(PathBuf::from).unwrap_or_else(|| PathBuf::from(".")),
     }
+}
 
-    false
+/// Why `-want` found nothing for a file: the closest known type, or the list of them.
+fn unknown_type_hint(want: &str, filename: &Path) -> Option<String> {
+    let directives = scan_directives(filename).ok()?;
+    let workdir = file_workdir(filename);
+    let cfg = load_defaults_cfg(Some(&workdir))?;
+    let known = known_types(&cfg, &directives, filename, &workdir);
+    let missing = if filename.file_name().is_some_and(|n| n.eq_ignore_ascii_case("package.json")) && cfg.builtin != Some(false) {
+        format!("no `{}` script", want)
+    } else {
+        format!("no recipe for -{}", want)
+    };
+    match suggest_type(want, &known) {
+        Some(s) => Some(format!("{}: {}; did you mean -{}?", filename.display(), missing, s)),
+        None if !known.is_empty() => Some(format!(
+            "{}: {}; known types: {}",
+            filename.display(),
+            missing,
+            known.iter().map(|k| format!("-{}", k)).collect::<Vec<_>>().join(" ")
+        )),
+        None => None,
+    }
+}
+
+fn build_file(type_expected: Option<&str>, filename: &Path) -> bool {
+    match resolve(type_expected, filename) {
+        Some(r) => run_command(&r.command(type_expected), &r.workdir),
+        None => false,
+    }
+}
+
+/// Print how `filename` would be built, without running anything.
+fn explain_file(type_expected: Option<&str>, filename: &Path) -> i32 {
+    let Some(r) = resolve(type_expected, filename) else {
+        if let Some(hint) = type_expected.and_then(|t| unknown_type_hint(t, filename)) {
+            println!("{}", hint);
+        }
+        println!("{}: no command found", filename.display());
+        return 1;
+    };
+    println!("{}", filename.display());
+    println!("  source:  {}", r.source);
+    println!("  workdir: {}", r.workdir.display());
+    println!("  command: {}", r.command(type_expected));
+    0
 }
 
 fn check_build_file(type_expected: Option<&str>, filename: &Path) -> i32 {
     if build_file(type_expected, filename) {
         0
     } else {
+        if let Some(hint) = type_expected.and_then(|t| unknown_type_hint(t, filename)) {
+            println!("{}", hint);
+        }
         println!("{}: no command found, skipping", filename.display());
         1
     }
 }
 
+/// Print the types available for `filename` and the command each would run.
+fn list_file(filename: &Path) -> i32 {
+    let scan = match scan_file(filename) {
+        Ok(scan) => scan,
+        Err(_) => {
+            println!("can not read {}", filename.display());
+            return 1;
+        }
+    };
+    let workdir = file_workdir(filename);
+    let mut types = match load_defaults_cfg(Some(&workdir)) {
+        Some(cfg) => known_types(&cfg, &scan.directives, filename, &workdir),
+        None => Vec::new(),
+    };
+    // A file delegating to a target also offers the target's types
+    if let Some((path, _)) = &scan.target {
+        let target = workdir.join(path);
+        let target_dir = file_workdir(&target);
+        if let (Ok(directives), Some(cfg)) = (scan_directives(&target), load_defaults_cfg(Some(&target_dir))) {
+            types.extend(known_types(&cfg, &directives, &target, &target_dir));
+            types.sort();
+            types.dedup();
+        }
+    }
+    let help = builtin_type_help(filename);
+    let mut rows: Vec<(String, String, Option<&String>)> = Vec::new();
+    for ty in std::iter::once(None).chain(types.iter().map(|t| Some(t.as_str()))) {
+        if let Some(r) = resolve(ty, filename) {
+            let label = ty.map(|t| format!("-{}", t)).unwrap_or_else(|| "(default)".into());
+            let note = match r.source {
+                Source::Builtin { .. } => ty.and_then(|t| help.get(&normalize_type(t))),
+                _ => None,
+            };
+            rows.push((label, r.command(ty), note));
+        }
+    }
+    println!("{}", filename.display());
+    if rows.is_empty() {
+        println!("  no recipes");
+        return 1;
+    }
+    let width = rows.iter().map(|(l, _, _)| l.len()).max().unwrap_or(0);
+    let cmd_width = rows.iter().filter(|(_, _, n)| n.is_some()).map(|(_, c, _)| c.len()).max().unwrap_or(0);
+    for (label, cmd, note) in rows {
+        match note {
+            Some(note) => println!("  {:width$}  {:cmd_width$}  ## {}", label, cmd, note, width = width, cmd_width = cmd_width),
+            None => println!("  {:width$}  {}", label, cmd, width = width),
+        }
+    }
+    0
+}
+
+/// Descriptions of built-in types, keyed by normalized type: the `##` help
+/// comments of a makefile's targets.
+fn builtin_type_help(path: &Path) -> HashMap<String, String> {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+    if !makefile::is_makefile(&name) {
+        return HashMap::new();
+    }
+    let text = std::fs::read_to_string(path).unwrap_or_default();
+    makefile::make_targets(&text)
+        .into_iter()
+        .filter_map(|t| Some((normalize_type(&t.name), t.help?)))
+        .collect()
+}
+
 fn config_path() -> Option<PathBuf> {
     // Determine a suitable config file path per platform.
     // Unix/macOS: $XDG_CONFIG_HOME/build.defaults or $HOME/.config/build.defaults
@@ -510,18 +1782,33 @@ fn short_help() -> String {
         "",
         "Usage:",
         "  ruild [-type] <file> [<file> ...]",
+        "  ruild --explain [-type] <file> [<file> ...]",
+        "  ruild --list <file> [<file> ...]",
+        "  ruild --export make|just|ninja|compile_commands|vscode [--force] [<file|dir> ...]",
         "  ruild --config_file",
-        "  ruild --dump_defaults",
+        "  ruild --dump_defaults [--platform <name>]",
+        "  ruild lsp       Language server for @build directives, over stdio",
         "",
         "Options:",
         "  --config_file   Print the config file location and exit",
         "  --dump_defaults Print bundled defaults for this platform and exit",
+        "  --explain       Show which recipe would run for each file, and where it comes from",
+        "  --list          List the types available for each file and what they run",
+        "  --export <format>",
+        "                  Print a Makefile, justfile, build.ninja or compile_commands.json for the files,",
+        "                  or write .vscode/tasks.json (--force replaces an existing one)",
+        "  --platform <name>",
+        "                  Act as if on macos, linux or windows (config sections, --dump_defaults)",
+        "  --diagnostics relative|json",
+        "                  Rewrite file:line locations in command output relative to the current",
+        "                  directory; json also prints each one as a JSON line on stdout",
         "",
         "Notes:",
         "  - Reads @build or @build-{type} from file comments",
         "  - %<token> -> \"<base><token>\", % -> <base>",
         "  - If no inline command, uses $XDG_CONFIG_HOME/build.defaults",
-        "    or ~/.config/build.defaults (Unix/macOS), or %APPDATA%\\build.defaults (Windows)",
+        "    or ~/.config/build.defaults (Unix/macOS), or %APPDATA%\\build.defaults (Windows),",
+        "    plus packs/*.defaults next to it and the project's .ruild/ directory",
         "  - Relative paths resolve from the file’s directory",
         "",
         "See README.md for examples.",
@@ -529,6 +1816,11 @@ fn short_help() -> String {
     msg.join("\n")
 }
 
+/// `lsp` or `lsp --stdio` alone; with anything else, `lsp` is a file to build.
+fn is_lsp_invocation(args: &[OsString]) -> bool {
+    matches!(args, [a] if a == "lsp") || matches!(args, [a, b] if a == "lsp" && b == "--stdio")
+}
+
 fn main() {
     // Mirrors Lua semantics:
     //   - Each "-<type>" flag sets the current build type for subsequent files
@@ -545,8 +1837,65 @@ fn main() {
         std::process::exit(0);
     }
 
+    // `--platform <name>` takes a value; pull it out before anything else
+    if let Some(i) = args.iter().position(|a| a == "--platform") {
+        let name = args.get(i + 1).map(|v| v.to_string_lossy().to_string());
+        match name.as_deref().and_then(Platform::named) {
+            Some(p) => {
+                let _ = PLATFORM_OVERRIDE.set(p);
+            }
+            None => {
+                eprintln!(
+                    "--platform expects one of macos, linux, windows (got {})",
+                    name.as_deref().unwrap_or("nothing")
+                );
+                std::process::exit(2);
+            }
+        }
+        args.drain(i..i + 2);
+    }
+
+    // `--diagnostics relative|json` also takes a value
+    if let Some(i) = args.iter().position(|a| a == "--diagnostics") {
+        let name = args.get(i + 1).map(|v| v.to_string_lossy().to_string());
+        match name.as_deref().and_then(diagnostics::Mode::named) {
+            Some(mode) => {
+                let _ = DIAGNOSTICS.set(mode);
+            }
+            None => {
+                eprintln!("--diagnostics expects relative or json (got {})", name.as_deref().unwrap_or("nothing"));
+                std::process::exit(2);
+            }
+        }
+        args.drain(i..i + 2);
+    }
+
+    // `ruild lsp` serves editors over stdio (clients may add `--stdio`)
+    if is_lsp_invocation(&args) {
+        std::process::exit(lsp::serve());
+    }
+
+    // `--export <format> [<file|dir> ...]` prints a build file for other tools
+    if let Some(i) = args.iter().position(|a| a == "--export") {
+        let format = args.get(i + 1).map(|v| v.to_string_lossy().to_string()).unwrap_or_default();
+        if !export::FORMATS.contains(&format.as_str()) {
+            eprintln!("--export expects one of {} (got {})", export::FORMATS.join(", "), if format.is_empty() { "nothing" } else { &format });
+            std::process::exit(2);
+        }
+        args.drain(i..i + 2);
+        let force = args.iter().any(|a| a == "--force");
+        let mut paths: Vec<PathBuf> = args
+            .iter()
+            .map(PathBuf::from)
+            .filter(|p| !p.to_string_lossy().starts_with('-'))
+            .collect();
+        if paths.is_empty() {
+            paths.push(PathBuf::from("."));
+        }
+        std::process::exit(export::export(&format, &paths, force));
+    }
+
     // Handle long options first to avoid conflict with -{type}
-    // We only support `--config_file` for now.
     for a in &args {
         let s = a.to_string_lossy();
         if s == "--config_file" {
@@ -565,27 +1914,46 @@ fn main() {
             std::process::exit(0);
         }
         if s == "--dump_defaults" {
-            print!("{}", BUNDLED_DEFAULTS);
+            match PLATFORM_OVERRIDE.get() {
+                Some(p) => print!("{}", bundled_defaults_for(&p.os).unwrap_or(BUNDLED_DEFAULTS)),
+                None => print!("{}", BUNDLED_DEFAULTS),
+            }
             std::process::exit(0);
         }
     }
 
     let mut res: i32 = 0;
     let mut ty: Option<String> = None;
+    let mut explain = false;
+    let mut list = false;
 
     for a in args {
         let s = a.to_string_lossy();
-        if s.starts_with("--") {
+        if s == "--explain" {
+            explain = true;
+        } else if s == "--list" {
+            list = true;
+        } else if s.starts_with("--") {
             // Unknown long option; show help and exit with error
             eprintln!("Unknown option: {}\n\n{}", s, short_help());
             std::process::exit(2);
         } else if s.starts_with('-') && s.len() > 1 {
             let t = s[1..].to_string();
-            println!("setting build type: {}", t);
+            if DIAGNOSTICS.get() == Some(&diagnostics::Mode::Json) {
+                eprintln!("setting build type: {}", t);
+            } else {
+                println!("setting build type: {}", t);
+            }
             ty = Some(t);
         } else {
             let path = Path::new(&*s);
-            res += check_build_file(ty.as_deref(), path);
+            res += if list {
+                list_file(path)
+            } else if explain {
+                explain_file(ty.as_deref(), path)
+            } else {
+                check_build_file(ty.as_deref(), path)
+            };
         }
     }
 
@@ -620,6 +1988,50 @@ mod tests {
         f.write_all(content.as_bytes()).unwrap();
     }
 
+    /// Sets an environment variable until dropped, putting the old value
+    /// back even when an assert fails. Hold `ENV_GUARD` while it lives.
+    struct EnvVar {
+        key: &'static str,
+        old: Option<std::ffi::OsString>,
+    }
+
+    impl EnvVar {
+        fn set(key: &'static str, value: impl AsRef<std::ffi::OsStr>) -> Self {
+            let old = env::var_os(key);
+            unsafe { env::set_var(key, value); }
+            EnvVar { key, old }
+        }
+    }
+
+    impl Drop for EnvVar {
+        fn drop(&mut self) {
+            match self.old.take() {
+                Some(v) => unsafe { env::set_var(self.key, v) },
+                None => unsafe { env::remove_var(self.key) },
+            }
+        }
+    }
+
+    fn lock_env() -> std::sync::MutexGuard<'static, ()> {
+        // A failed test must not take the rest down with a poisoned lock
+        ENV_GUARD.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// A personal config directory holding `defaults` as build.defaults,
+    /// in use through XDG_CONFIG_HOME until dropped.
+    struct UserConfig {
+        dir: PathBuf,
+        _xdg: EnvVar,
+        _lock: std::sync::MutexGuard<'static, ()>,
+    }
+
+    fn user_config(defaults: &str) -> UserConfig {
+        let lock = lock_env();
+        let dir = tmp_dir("xdg");
+        write_file(&dir.join("build.defaults"), defaults);
+        UserConfig { _xdg: EnvVar::set("XDG_CONFIG_HOME", &dir), dir, _lock: lock }
+    }
+
     #[test]
     fn test_is_comment_variants() {
         assert_eq!(is_comment("# hello").as_deref(), Some("hello"));
@@ -681,7 +2093,7 @@ mod tests {
         let marker = d.join("marker.txt");
         assert!(!marker.exists());
         // Command writes to a file in the working directory; ensure it lands in `d`.
-        let ok = run_command("echo hi > marker.txt", "base.", &d, &d.join("dummy.txt"), None);
+        let ok = run_command("echo hi > marker.txt", &d);
         assert!(ok);
         assert!(marker.exists());
     }
@@ -766,6 +2178,1186 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_platform_sections_select_rules() {
+        let cfg_text = "svg: generic\n[os=macos]\nsvg: qlmanage %svg\n[os=linux arch=x86_64,aarch64]\nc: gcc %c\n[host=build-*]\nmd: remote\n[*]\ntxt: plain\n";
+        let mac = Platform { os: "macos".into(), arch: "aarch64".into(), host: "laptop".into() };
+        let src = Origin::default();
+        let cmd = |cfg: &DefaultsCfg, ext: &str| cfg.ext_map.get(ext).map(|r| r.cmd.clone());
+        let cfg = parse_defaults_for(cfg_text, &mac, &src);
+        assert_eq!(cmd(&cfg, "svg").as_deref(), Some("qlmanage %svg"));
+        assert!(!cfg.ext_map.contains_key("c"));
+        assert!(!cfg.ext_map.contains_key("md"));
+        assert_eq!(cmd(&cfg, "txt").as_deref(), Some("plain"));
+
+        let linux = Platform { os: "linux".into(), arch: "x86_64".into(), host: "build-01".into() };
+        let cfg = parse_defaults_for(cfg_text, &linux, &src);
+        assert_eq!(cmd(&cfg, "svg").as_deref(), Some("generic"));
+        assert_eq!(cmd(&cfg, "c").as_deref(), Some("gcc %c"));
+        assert_eq!(cmd(&cfg, "md").as_deref(), Some("remote"));
+
+        // `unix` covers both Linux and macOS
+        let cfg = parse_defaults_for("[os=unix]\nsh: bash %sh\n", &mac, &src);
+        assert!(cfg.ext_map.contains_key("sh"));
+    }
+
+    #[test]
+    fn test_bundled_defaults_for_platform_names() {
+        assert!(bundled_defaults_for("macos").unwrap().contains("qlmanage"));
+        assert!(bundled_defaults_for("linux").unwrap().contains("(Unix/macOS/Linux)"));
+        assert!(bundled_defaults_for("windows").unwrap().contains("(Windows)"));
+        assert!(bundled_defaults_for("plan9").is_none());
+        assert!(glob_match("build-*", "build-01"));
+        assert!(glob_match("*.svg", "logo.svg"));
+        assert!(!glob_match("a?c", "abbc"));
+    }
+
+    #[test]
+    fn test_personal_config_beats_packs() {
+        let cfg = user_config("md: echo user\npack media off\n");
+        write_file(&cfg.dir.join("packs").join("latex.defaults"), "tex: echo latex-pack\nmd: echo pack\n");
+        write_file(&cfg.dir.join("packs").join("media.defaults"), "mov: echo media\n");
+
+        // Disabled packs are skipped
+        let loaded = load_defaults_cfg(Some(&tmp_dir("packs"))).unwrap();
+        assert_eq!(loaded.ext_map["md"].cmd, "echo user");
+        assert_eq!(loaded.ext_map["tex"].origin.pack.as_deref(), Some("latex"));
+        assert!(!loaded.ext_map.contains_key("mov"));
+    }
+
+    #[test]
+    fn test_project_config_reenables_packs_and_overrides() {
+        let cfg = user_config("md: echo user\npack media off\n");
+        write_file(&cfg.dir.join("packs").join("media.defaults"), "mov: echo media\n");
+        let proj = tmp_dir("packs_proj");
+        let sub = proj.join("docs");
+        write_file(&proj.join(".ruild").join("build.defaults"), "pack media on\nmd: echo project\n");
+        write_file(&proj.join(".ruild").join("packs").join("docs.defaults"), "rst: echo docs-pack\n");
+
+        let loaded = load_defaults_cfg(Some(&sub)).unwrap();
+        assert_eq!(loaded.ext_map["md"].cmd, "echo project");
+        assert_eq!(loaded.ext_map["mov"].cmd, "echo media");
+        assert_eq!(loaded.ext_map["rst"].origin.pack.as_deref(), Some("docs"));
+
+        let file = sub.join("intro.rst");
+        write_file(&file, "no directive\n");
+        let r = resolve(None, &file).unwrap();
+        assert_eq!(r.source.to_string(), format!(
+            "extension rule `rst` (pack docs, {}:1)",
+            proj.join(".ruild").join("packs").join("docs.defaults").display()
+        ));
+    }
+
+    #[test]
+    fn test_resolve_reports_inline_line() {
+        let d = tmp_dir("resolve_inline");
+        let file = d.join("doc.md");
+        write_file(&file, "title\n<!-- @build echo multi\nline -->\n<!-- @build-pdf echo pdf -->\n");
+        let r = resolve(Some("pdf"), &file).unwrap();
+        assert_eq!(r.tpl, "echo pdf");
+        assert!(matches!(r.source, Source::Inline { line: 4 }));
+        assert_eq!(r.workdir, fs::canonicalize(&d).unwrap());
+    }
+
+    #[test]
+    fn test_include_resolves_relative_and_detects_cycles() {
+        let d = tmp_dir("include");
+        let shared = d.join("shared");
+        write_file(&shared.join("a.defaults"), "md: echo a\ninclude ../main.defaults\n");
+        write_file(&shared.join("b.defaults"), "tex: echo b\nbogus line\n");
+        write_file(&d.join("main.defaults"), "md: echo main\ninclude shared/*.defaults\ninclude missing.defaults\nrst: echo after\n");
+
+        let linux = Platform { os: "linux".into(), arch: "x86_64".into(), host: "h".into() };
+        let cfg = read_cfg_file(&d.join("main.defaults"), &linux, None).unwrap();
+        let cmd = |ext: &str| cfg.ext_map.get(ext).map(|r| r.cmd.clone());
+        // Included rules land where the include is: a.defaults overrides main's md
+        assert_eq!(cmd("md").as_deref(), Some("echo a"));
+        assert_eq!(cmd("tex").as_deref(), Some("echo b"));
+        assert_eq!(cmd("rst").as_deref(), Some("echo after"));
+        assert_eq!(cfg.ext_map["tex"].origin.path, shared.join("b.defaults"));
+        assert_eq!(cfg.ext_map["tex"].origin.line, 1);
+
+        let msgs: Vec<String> = cfg.diagnostics.iter().map(|(o, m)| format!("{}: {}", o, m)).collect();
+        assert_eq!(msgs.len(), 3, "{:?}", msgs);
+        assert!(msgs[0].starts_with(&format!("{}:2: include cycle: ", shared.join("a.defaults").display())));
+        assert_eq!(msgs[1], format!("{}:2: unrecognized line `bogus line`", shared.join("b.defaults").display()));
+        assert!(msgs[2].starts_with(&format!("{}:3: cannot include ", d.join("main.defaults").display())));
+    }
+
+    #[test]
+    fn test_config_messages_reported_once_per_run() {
+        let cfg = user_config("md: echo md\nbogus line\n");
+        let d = tmp_dir("report_once");
+        let msg = format!("{}:2: unrecognized line `bogus line`", cfg.dir.join("build.defaults").display());
+        load_defaults_cfg(Some(&d)).unwrap();
+        // Reading the config again doesn't repeat the message
+        load_defaults_cfg(Some(&d)).unwrap();
+        assert!(!report_once(msg));
+    }
+
+    #[test]
+    fn test_bundled_defaults_parse_cleanly() {
+        let linux = Platform { os: "linux".into(), arch: "x86_64".into(), host: "h".into() };
+        for text in [BUNDLED_MACOS, BUNDLED_UNIX, BUNDLED_WINDOWS] {
+            let cfg = parse_defaults_for(text, &linux, &Origin::default());
+            assert!(cfg.diagnostics.is_empty(), "{:?}", cfg.diagnostics);
+        }
+    }
+
+    #[test]
+    fn test_alias_chain_and_type_suggestions() {
+        let linux = Platform { os: "linux".into(), arch: "x86_64".into(), host: "h".into() };
+        let cfg = parse_defaults_for(
+            "alias pdf = latex, xelatex\nalias print = pdf\nmd -html: pandoc -s -o %html %md\nmdx: mdx-build %mdx\nfile:package.json -start: npm start\nfile:package.json: npm run build\n",
+            &linux,
+            &Origin::default(),
+        );
+        assert_eq!(type_chain(&cfg, "print"), vec!["print", "pdf", "latex", "xelatex"]);
+
+        // Typed file rules win over an earlier untyped one
+        assert_eq!(match_file_rule(&cfg, "package.json", Some("start")).unwrap().cmd, "npm start");
+        assert_eq!(match_file_rule(&cfg, "package.json", None).unwrap().cmd, "npm run build");
+
+        let directives = vec![Directive { ty: "latex".into(), tpl: "pdflatex %tex".into(), line: 1, sidecar: None }];
+        let known = known_types(&cfg, &directives, Path::new("notes.md"), Path::new("/nonexistent"));
+        assert_eq!(known, vec!["html", "latex", "pdf", "print"]);
+        assert_eq!(suggest_type("htlm", &known), Some("html"));
+        assert_eq!(suggest_type("lat", &known), Some("latex"));
+        assert_eq!(suggest_type("zzzzzz", &known), None);
+    }
+
+    #[test]
+    fn test_typed_extension_rules() {
+        let _cfg = user_config("c: cc %c\nc -asm: cc -S %c\ncpp: c++ %cpp\nmdhtml: legacy\n");
+        let d = tmp_dir("typed_ext");
+        let file = d.join("t.c");
+        write_file(&file, "int x;\n");
+        let r = resolve(Some("asm"), &file).unwrap();
+        assert_eq!(r.tpl, "cc -S %c");
+        assert_eq!(r.source.to_string(), format!("extension rule `c -asm` ({}:2)", _cfg.dir.join("build.defaults").display()));
+        // Other extensions starting with the file's own are not types of it,
+        // and an unknown type doesn't fall back to the plain rule
+        assert!(resolve(Some("pp"), &file).is_none());
+        assert_eq!(unknown_type_hint("asn", &file).as_deref(), Some(format!("{}: no recipe for -asn; did you mean -asm?", file.display()).as_str()));
+        assert_eq!(resolve(Some("build"), &file).unwrap().tpl, "cc %c");
+        let cfg = load_defaults_cfg(Some(&d)).unwrap();
+        assert_eq!(known_types(&cfg, &[], &file, &d), vec!["asm"]);
+        assert!(known_types(&cfg, &[], Path::new("notes.md"), &d).is_empty());
+    }
+
+    #[test]
+    fn test_resolve_follows_alias_to_inline_type() {
+        let _cfg = user_config("alias pdf = latex, xelatex\n");
+        let d = tmp_dir("alias_file");
+        let file = d.join("paper.tex");
+        write_file(&file, "% text\n# @build-xelatex xelatex %tex\n# @build-latex pdflatex %tex\n");
+        let r = resolve(Some("pdf"), &file).unwrap();
+        assert_eq!(r.tpl, "pdflatex %tex");
+        assert!(matches!(r.source, Source::Inline { line: 3 }));
+        assert!(resolve(Some("nope"), &file).is_none());
+    }
+
+    #[test]
+    fn test_builtin_layer_runs_after_file_rules() {
+        let _cfg = user_config("md: echo md\n");
+        let d = tmp_dir("builtin_proj");
+        let book = d.join("book.toml");
+        write_file(&book, "[book]\n");
+        let r = resolve(None, &book).unwrap();
+        assert_eq!(r.tpl, "mdbook build");
+        assert!(matches!(r.source, Source::Builtin { .. }));
+
+        // The user's own file: rules come first
+        write_file(&d.join(".ruild").join("build.defaults"), "file:book.toml: echo mine\n");
+        assert_eq!(resolve(None, &book).unwrap().tpl, "echo mine");
+    }
+
+    #[test]
+    fn test_builtin_layer_can_be_disabled() {
+        let _cfg = user_config("md: echo md\n");
+        let d = tmp_dir("builtin_off");
+        let book = d.join("book.toml");
+        write_file(&book, "[book]\n");
+        write_file(&d.join(".ruild").join("build.defaults"), "builtin off\n");
+        assert!(resolve(None, &book).is_none());
+    }
+
+    #[test]
+    fn test_project_root_discovery() {
+        let d = tmp_dir("project_root");
+        write_file(&d.join("Cargo.toml"), "[package]\nname = \"demo\"\n");
+        write_file(&d.join("web").join("package.json"), "{}\n");
+        let src = d.join("src").join("deep");
+        fs::create_dir_all(&src).unwrap();
+
+        let p = find_project_root(&src, Some("rs")).unwrap();
+        assert_eq!(p.kind, project::ProjectKind::Cargo);
+        assert_eq!(p.root, d);
+        // Only kinds claiming the extension count: Markdown stays a document
+        assert!(find_project_root(&src, Some("md")).is_none());
+        // The nearest matching marker wins
+        let p = find_project_root(&d.join("web"), Some("ts")).unwrap();
+        assert_eq!(p.kind, project::ProjectKind::Node);
+        assert_eq!(project::project_root_command(&p, Some("test")), None);
+        write_file(&d.join("web").join("package.json"), "{\"scripts\": {\"test\": \"vitest\"}}\n");
+        assert_eq!(project::project_root_command(&p, Some("test")).as_deref(), Some("{{pm_test}}"));
+        // No `build` script: nothing to run, rather than a failing package manager
+        assert_eq!(project::project_root_command(&p, None), None);
+        assert_eq!(project::project_root_command(&p, Some("frobnicate")), None);
+
+        write_file(&d.join("CMakeLists.txt"), "project(x)\n");
+        let p = find_project_root(&src, Some("cpp")).unwrap();
+        assert_eq!(project::project_root_command(&p, None).as_deref(), Some("cmake -S . -B build && cmake --build build"));
+        write_file(&d.join("build").join("CMakeCache.txt"), "");
+        assert_eq!(project::project_root_command(&p, None).as_deref(), Some("cmake --build build"));
+    }
+
+    #[test]
+    fn test_project_root_stops_at_repository() {
+        let home = tmp_dir("project_home");
+        write_file(&home.join("Makefile"), "all:\n");
+        let repo = home.join("code").join("tool");
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        write_file(&repo.join("src").join("main.c"), "int main(void) { return 0; }\n");
+        assert!(find_project_root(&repo.join("src"), Some("c")).is_none());
+        // Outside any repository the walk goes on up
+        assert_eq!(find_project_root(&home.join("scratch"), Some("c")).unwrap().root, home);
+        // A marker at the repository root itself still counts
+        write_file(&repo.join("Makefile"), "all:\n");
+        assert_eq!(find_project_root(&repo.join("src"), Some("c")).unwrap().root, repo);
+    }
+
+    #[test]
+    fn test_resolve_builds_enclosing_project_from_root() {
+        let _cfg = user_config("go: go build -o %out %go\n");
+        let d = tmp_dir("project_make");
+        write_file(&d.join("Makefile"), "all:\n");
+        let file = d.join("src").join("util.c");
+        write_file(&file, "int x;\n");
+        let r = resolve(None, &file).unwrap();
+        assert_eq!(r.tpl, "make");
+        assert_eq!(r.workdir, d);
+        assert!(matches!(r.source, Source::Project { .. }));
+        let cmd = expand_command("echo {{root}} {{dir}}", &r.base, &r.workdir, &file, None);
+        assert_eq!(cmd, format!("echo \"{}\" \"{}\"", d.display(), d.join("src").display()));
+
+        // An inline @build still wins
+        write_file(&file, "// @build cc -c %c\n");
+        assert_eq!(resolve(None, &file).unwrap().tpl, "cc -c %c");
+    }
+
+    #[test]
+    fn test_compile_commands_replayed_for_single_files() {
+        let _cfg = user_config("c: gcc -Wall %c -o %out\n");
+        let d = fs::canonicalize(tmp_dir("compdb")).unwrap();
+        write_file(&d.join("CMakeLists.txt"), "project(x C)\n");
+        write_file(&d.join("src/a.c"), "int a;\n");
+        write_file(&d.join("src/b.c"), "int b;\n");
+        write_file(&d.join("src/c.c"), "int c;\n");
+        let build = d.join("build");
+        let db = serde_json::json!([
+            { "directory": build, "file": "../src/a.c",
+              "command": r#"/usr/bin/cc "-DNAME=\"x y\"" -I../include -O2 -MD -MT a.o -MF a.o.d -o a.o -c ../src/a.c"# },
+            { "directory": build, "file": d.join("src/b.c"),
+              "arguments": ["cc", "-Iinc dir", "-c", "-o", "b.o", d.join("src/b.c").to_str().unwrap()] },
+        ]);
+        write_file(&build.join("compile_commands.json"), &db.to_string());
+
+        let r = resolve(None, &d.join("src/a.c")).unwrap();
+        assert!(matches!(r.source, Source::CompileDb { .. }));
+        assert_eq!(r.workdir, build);
+        assert_eq!(r.tpl, r#"/usr/bin/cc "-DNAME=\"x y\"" -I../include -O2 -MD -MT a.o -MF a.o.d -o a.o -c ../src/a.c"#);
+        let r = resolve(Some("syntax"), &d.join("src/a.c")).unwrap();
+        assert_eq!(r.tpl, r#"/usr/bin/cc -fsyntax-only "-DNAME=\"x y\"" -I../include -O2 ../src/a.c"#);
+        let r = resolve(Some("syntax"), &d.join("src/b.c")).unwrap();
+        assert_eq!(r.tpl, format!("cc -fsyntax-only \"-Iinc dir\" {}", d.join("src/b.c").display()));
+
+        // Files missing from the database and other types build the project
+        assert!(matches!(resolve(None, &d.join("src/c.c")).unwrap().source, Source::Project { .. }));
+        assert!(matches!(resolve(Some("test"), &d.join("src/a.c")).unwrap().source, Source::Project { .. }));
+    }
+
+    #[test]
+    fn test_compile_commands_run_verbatim() {
+        let _cfg = user_config("c: gcc -Wall %c -o %out\n");
+        let d = fs::canonicalize(tmp_dir("compdb_verbatim")).unwrap();
+        fs::create_dir_all(d.join(".git")).unwrap();
+        write_file(&d.join("a.c"), "int a;\n");
+        let db = serde_json::json!([
+            { "directory": d, "file": "a.c", "command": r#"cc "-DFMT=\"%d\"" -DTPL={{x}} -c a.c"# },
+        ]);
+        write_file(&d.join("compile_commands.json"), &db.to_string());
+        let r = resolve(None, &d.join("a.c")).unwrap();
+        assert_eq!(r.command(None), r#"cc "-DFMT=\"%d\"" -DTPL={{x}} -c a.c"#);
+        // ...through a delegating file too
+        write_file(&d.join("part.h"), "// @build-target a.c\n");
+        assert_eq!(resolve(None, &d.join("part.h")).unwrap().command(None), r.command(None));
+    }
+
+    #[test]
+    fn test_tex_magic_comments() {
+        assert_eq!(detect("% @build pdflatex %tex"), Some(("".into(), "pdflatex %tex".into())));
+        assert_eq!(tex_magic("%!TeX TS-program = xelatex"), Some(("program".into(), "xelatex".into())));
+    }
+
+    #[test]
+    fn test_tex_root_delegation() {
+        let _cfg = user_config("tex: latexmk -pdf %tex\ntex -clean: latexmk -C %tex\n");
+        let book = fs::canonicalize(tmp_dir("tex_book")).unwrap();
+        write_file(&book.join("main.tex"), "% !TEX program = xelatex\n\\documentclass{book}\n");
+        let intro = book.join("chapters").join("intro.tex");
+        write_file(&intro, "% !TEX root = ../main.tex\n\\chapter{Intro}\n");
+        let r = resolve(None, &intro).unwrap();
+        assert_eq!(r.workdir, book);
+        assert!(matches!(&r.source, Source::Target { line: 1, via, .. } if matches!(**via, Source::TexProgram { line: 1 })));
+        assert_eq!(expand_command(&r.tpl, &r.base, &r.workdir, &r.file, None), "latexmk -pdfxe -interaction=nonstopmode \"main.tex\"");
+        // Other types come from the root's usual rules; an inline @build in the chapter still wins
+        assert_eq!(resolve(Some("clean"), &intro).unwrap().tpl, "latexmk -C %tex");
+        write_file(&intro, "% !TEX root = ../main.tex\n% @build pdflatex %tex\n");
+        assert_eq!(resolve(None, &intro).unwrap().tpl, "pdflatex %tex");
+    }
+
+    #[test]
+    fn test_build_target_delegation_and_cycles() {
+        let _cfg = user_config("tex: latexmk -pdf %tex\n");
+        let book = fs::canonicalize(tmp_dir("target_book")).unwrap();
+        write_file(&book.join("notes").join("part.md"), "<!-- @build-target ../book.md -->\n# Part\n");
+        write_file(&book.join("book.md"), "<!-- @build-pdf pandoc %md -o %pdf -->\n");
+        let r = resolve(Some("pdf"), &book.join("notes").join("part.md")).unwrap();
+        assert_eq!((r.tpl.as_str(), r.base.as_str(), r.workdir.as_path()), ("pandoc %md -o %pdf", "book.", book.as_path()));
+        write_file(&book.join("a.md"), "<!-- @build-target b.md -->\n");
+        write_file(&book.join("b.md"), "<!-- @build-target a.md -->\n");
+        assert!(resolve(None, &book.join("a.md")).is_none());
+    }
+
+    #[test]
+    fn test_editor_recipe_parsing() {
+        use modeline::editor_recipe;
+        let cmd = |text: &str| editor_recipe(text).map(|e| (e.cmd, e.line));
+        assert_eq!(cmd("/* -*- mode: c; compile-command: \"gcc -o \\\"x y\\\" x.c\" -*- */\n"), Some(("gcc -o \"x y\" x.c".into(), 1)));
+        assert_eq!(cmd("#!/bin/sh\n# -*- compile-command: \"sh -n run.sh\" -*-\n"), Some(("sh -n run.sh".into(), 2)));
+        let locals = "int x;\n/* Local Variables: */\n/* mode: c */\n/* compile-command: \"make -k x\" */\n/* End: */\n";
+        assert_eq!(cmd(locals), Some(("make -k x".into(), 4)));
+        assert_eq!(cmd("// vim: set ts=4 makeprg=gcc\\ -Wall\\ %\\ -o\\ %<: \nint x;\n"), Some(("gcc -Wall {{file}} -o {{file_stem}}".into(), 1)));
+        assert_eq!(cmd("x\n# vim: sw=2:mp=make\\ test\n"), Some(("make test".into(), 2)));
+        assert_eq!(cmd("// vim: set ts=4:\n// compile-command: \"nope\"\n"), None);
+    }
+
+    #[test]
+    fn test_editor_compile_settings_are_opt_in() {
+        let cfg = user_config("c: cc %c\n");
+        let d = tmp_dir("modeline");
+        let file = d.join("old.c");
+        write_file(&file, "/* -*- compile-command: \"gcc -O2 -o old old.c\" -*- */\nint main(void) { return 0; }\n");
+        assert_eq!(resolve(None, &file).unwrap().tpl, "cc %c");
+        write_file(&cfg.dir.join("build.defaults"), "modelines on\nc: cc %c\nc -test: cc -DTEST %c\n");
+        let r = resolve(None, &file).unwrap();
+        assert_eq!(r.tpl, "gcc -O2 -o old old.c");
+        assert!(matches!(r.source, Source::Editor { line: 1, .. }));
+        // Typed requests and @build directives are unaffected
+        assert_eq!(resolve(Some("test"), &file).unwrap().tpl, "cc -DTEST %c");
+        write_file(&file, "/* -*- compile-command: \"gcc old.c\" -*- */\n// @build clang %c\n");
+        assert_eq!(resolve(None, &file).unwrap().tpl, "clang %c");
+    }
+
+    #[test]
+    fn test_editor_recipes_keep_percent_literal() {
+        let _cfg = user_config("modelines on\n");
+        let d = tmp_dir("modeline_percent");
+        let file = d.join("log.sh");
+        write_file(&file, "# -*- compile-command: \"sh log.sh > log-$(date +%Y).txt\" -*-\n");
+        assert_eq!(resolve(None, &file).unwrap().command(None), "sh log.sh > log-$(date +%Y).txt");
+        write_file(&file, "# vim: set makeprg=printf\\ '\\%s\\\\n'\\ %\\ >\\ %<.out:\n");
+        assert_eq!(resolve(None, &file).unwrap().command(None), "printf '%s\\n' \"log.sh\" > \"log\".out");
+    }
+
+    #[test]
+    fn test_notebook_cells_and_metadata_carry_directives() {
+        let d = tmp_dir("notebook");
+        let nb = serde_json::json!({
+            "cells": [
+                { "cell_type": "markdown", "metadata": {},
+                  "source": ["# Report\n", "<!-- @build-html jupyter nbconvert --to html\n", "     --no-input %ipynb -->\n"] },
+                { "cell_type": "code", "metadata": { "ruild": { "build-run": "papermill %ipynb out.ipynb" } },
+                  "source": "import pandas\n# @build jupyter nbconvert --execute --to notebook %ipynb", "outputs": [] },
+            ],
+            "metadata": { "ruild": { "build-pdf": ["jupyter nbconvert --to pdf %ipynb", "open %pdf"], "note": "ignored" } },
+            "nbformat": 4, "nbformat_minor": 5,
+        });
+        let text = serde_json::to_string_pretty(&nb).unwrap();
+        let file = d.join("report.ipynb");
+        write_file(&file, &text);
+        let line_of = |needle: &str| text.lines().position(|l| l.contains(needle)).unwrap() + 1;
+
+        let found: Vec<(String, String, usize)> = scan_directives(&file).unwrap().into_iter().map(|d| (d.ty, d.tpl, d.line)).collect();
+        assert_eq!(found, vec![
+            ("pdf".into(), "jupyter nbconvert --to pdf %ipynb && open %pdf".into(), line_of("--to pdf")),
+            ("html".into(), "jupyter nbconvert --to html --no-input %ipynb".into(), line_of("@build-html")),
+            ("run".into(), "papermill %ipynb out.ipynb".into(), line_of("papermill")),
+            ("".into(), "jupyter nbconvert --execute --to notebook %ipynb".into(), line_of("@build jupyter")),
+        ]);
+
+        // Not a notebook after all: plain line scan
+        write_file(&file, "# @build echo broken\n{");
+        assert_eq!(scan_directives(&file).unwrap()[0].tpl, "echo broken");
+    }
+
+    #[test]
+    fn test_front_matter_recipes() {
+        let d = tmp_dir("front_matter");
+        let post = d.join("post.md");
+        write_file(&post, concat!(
+            "---\n",
+            "title: \"Hello: world\"\n",
+            "build: pandoc %md -o %html   # quick preview\n",
+            "build-pdf:\n",
+            "  - pandoc %md -o %pdf\n",
+            "  - 'open %pdf'\n",
+            "build-slides: [\"pandoc -t beamer %md -o slides.pdf\", echo done]\n",
+            "build-notes: |\n",
+            "  mkdir -p out\n",
+            "  pandoc %md -o out/notes.html\n",
+            "tags: [build]\n",
+            "...\n",
+            "<!-- @build-html hugo -->\n",
+        ));
+        let found: Vec<(String, String, usize)> = scan_directives(&post).unwrap().into_iter().map(|d| (d.ty, d.tpl, d.line)).collect();
+        assert_eq!(found, vec![
+            ("".into(), "pandoc %md -o %html".into(), 3),
+            ("pdf".into(), "pandoc %md -o %pdf && open %pdf".into(), 4),
+            ("slides".into(), "pandoc -t beamer %md -o slides.pdf && echo done".into(), 7),
+            ("notes".into(), "mkdir -p out && pandoc %md -o out/notes.html".into(), 8),
+            ("html".into(), "hugo".into(), 13),
+        ]);
+
+        let hugo = d.join("page.md");
+        write_file(&hugo, "+++\ntitle = \"Page\"\nbuild-html = \"hugo --minify\"\nbuild = [\"hugo\", \"echo ok\"]\n+++\nBody\n");
+        let found: Vec<(String, String, usize)> = scan_directives(&hugo).unwrap().into_iter().map(|d| (d.ty, d.tpl, d.line)).collect();
+        assert_eq!(found, vec![("html".into(), "hugo --minify".into(), 3), ("".into(), "hugo && echo ok".into(), 4)]);
+
+        // YAML files keep their top-level keys to themselves
+        let ci = d.join("ci.yml");
+        write_file(&ci, "---\nbuild: make\n---\n");
+        assert!(scan_directives(&ci).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sidecar_file_recipes() {
+        let _cfg = user_config("svg: rsvg-convert %svg -o %png\n");
+        let d = tmp_dir("sidecar");
+        let logo = d.join("logo.svg");
+        write_file(&logo, "<svg xmlns=\"http://www.w3.org/2000/svg\"/>\n");
+        write_file(&d.join("logo.svg.build"), "# recipes for logo.svg\n@build inkscape %svg -o %png\n@build-pdf rsvg-convert -f pdf %svg > %pdf\n");
+
+        let r = resolve(None, &logo).unwrap();
+        assert_eq!(r.tpl, "inkscape %svg -o %png");
+        assert!(matches!(&r.source, Source::Sidecar { path, line: 2 } if path.ends_with("logo.svg.build")));
+        assert_eq!(resolve(Some("pdf"), &logo).unwrap().tpl, "rsvg-convert -f pdf %svg > %pdf");
+
+        // Inline directives come first
+        write_file(&logo, "<!-- @build svgo %svg -->\n<svg/>\n");
+        assert_eq!(resolve(None, &logo).unwrap().tpl, "svgo %svg");
+    }
+
+    #[test]
+    fn test_sidecar_directory_recipes() {
+        let _cfg = user_config("svg: rsvg-convert %svg -o %png\n");
+        let d = tmp_dir("sidecar_dir");
+        write_file(&d.join("logo.svg"), "<svg/>\n");
+        write_file(&d.join(".build"), "# assets\n*.heic -jpg: magick %heic %jpg\ndata.json -pretty: jq . %json > %pretty.json\n*.svg -ico: magick %svg %ico\n");
+        fs::write(d.join("photo.heic"), [0u8, 159, 146, 150, 255]).unwrap();
+        write_file(&d.join("data.json"), "{}\n");
+
+        assert_eq!(resolve(Some("ico"), &d.join("logo.svg")).unwrap().tpl, "magick %svg %ico");
+        let r = resolve(Some("jpg"), &d.join("photo.heic")).unwrap();
+        assert!(matches!(&r.source, Source::Sidecar { path, line: 2 } if path.ends_with(".build")));
+        assert_eq!(r.tpl, "magick %heic %jpg");
+        assert_eq!(resolve(Some("pretty"), &d.join("data.json")).unwrap().tpl, "jq . %json > %pretty.json");
+        assert!(resolve(Some("jpg"), &d.join("data.json")).is_none());
+    }
+
+    #[test]
+    fn test_makefile_targets_become_types() {
+        let text = concat!(
+            "PREFIX ?= /usr/local\n",
+            "CFLAGS := -O2\n",
+            ".PHONY: all install clean\n",
+            "all: tool ## Build everything\n",
+            "\n",
+            "## Install into $(PREFIX)\n",
+            "install: all\n",
+            "\tinstall -m 755 tool $(PREFIX)/bin\n",
+            "%.o: %.c\n",
+            "\t$(CC) -c $<\n",
+            "tool docs-html: main.o\n",
+            "tool: CFLAGS += -g\n",
+            "define HELP\n",
+            "fake: target\n",
+            "endef\n",
+            "clean:: ## Remove build output\n",
+        );
+        let targets: Vec<(String, Option<String>)> = makefile::make_targets(text).into_iter().map(|t| (t.name, t.help)).collect();
+        assert_eq!(targets, vec![
+            ("all".into(), Some("Build everything".into())),
+            ("install".into(), Some("Install into $(PREFIX)".into())),
+            ("tool".into(), None),
+            ("docs-html".into(), None),
+            ("clean".into(), Some("Remove build output".into())),
+        ]);
+
+        let d = tmp_dir("makefile");
+        let mk = d.join("Makefile");
+        write_file(&mk, text);
+        write_file(&d.join("tools.mk"), "lint: ## Run linters\n\tshellcheck *.sh\n");
+        assert_eq!(project_command_for_file(None, &mk).as_deref(), Some("make"));
+        assert_eq!(project_command_for_file(Some("install"), &mk).as_deref(), Some("make install"));
+        assert_eq!(project_command_for_file(Some("docshtml"), &mk).as_deref(), Some("make docs-html"));
+        assert_eq!(project_command_for_file(Some("deploy"), &mk), None);
+        assert_eq!(project_command_for_file(Some("lint"), &d.join("tools.mk")).as_deref(), Some("make -f \"tools.mk\" lint"));
+        assert_eq!(builtin_types(&mk, &d), vec!["all", "install", "tool", "docs-html", "clean"]);
+        assert_eq!(builtin_type_help(&mk).get("clean").map(String::as_str), Some("Remove build output"));
+    }
+
+    fn export_docs_tree() -> PathBuf {
+        let root = std::fs::canonicalize(tmp_dir("export")).unwrap();
+        write_file(&root.join("docs").join("guide.md"), "<!-- @build pandoc -o %pdf %md -->\n<!-- @build-html pandoc -s -o %html %md -->\n");
+        write_file(&root.join("notes.md"), "no directives here\n");
+        write_file(&root.join("VERSION"), "1.0\n");
+        root
+    }
+
+    #[test]
+    fn test_export_collects_declared_recipes() {
+        let _cfg = user_config("md: pandoc -o %pdf %md\nfile:VERSION -bump: echo next > %\n");
+        let root = export_docs_tree();
+        let entries = export::collect(std::slice::from_ref(&root), &root, false);
+        let names: Vec<String> = entries.iter().map(export::Entry::name).collect();
+        assert_eq!(names, vec!["VERSION-bump", "docs-guide-md", "docs-guide-md-html"]);
+        assert_eq!(entries[1].dir, "docs");
+        assert_eq!(entries[1].outputs, vec!["docs/guide.pdf"]);
+        assert_eq!(entries[2].outputs, vec!["docs/guide.html"]);
+
+        // Files named explicitly fall back to extension rules
+        let named = export::collect(&[root.join("notes.md")], &root, false);
+        assert_eq!(named.len(), 1);
+        assert_eq!(named[0].command, "pandoc -o \"notes.pdf\" \"notes.md\"");
+    }
+
+    #[test]
+    fn test_export_make_and_just() {
+        let _cfg = user_config("md: pandoc -o %pdf %md\n");
+        let root = export_docs_tree();
+        let entries = export::collect(std::slice::from_ref(&root), &root, false);
+        let make = export::render_make(&entries);
+        assert!(make.contains("all: docs-guide-md\n"));
+        assert!(make.contains("docs-guide-md: docs/guide.pdf\ndocs/guide.pdf: docs/guide.md\n\tcd docs && pandoc -o \"guide.pdf\" \"guide.md\"\n"));
+        assert!(make.contains("docs-guide-md-html: docs/guide.html\n"));
+        let just = export::render_just(&entries);
+        assert!(just.contains("# docs/guide.md -html\ndocs-guide-md-html:\n    cd docs && pandoc -s -o \"guide.html\" \"guide.md\"\n"));
+    }
+
+    #[test]
+    fn test_export_make_escapes_paths_and_separates_names() {
+        let _cfg = user_config("md: pandoc -o %pdf %md\n");
+        let root = export_docs_tree();
+        write_file(&root.join("docs-guide.md"), "<!-- @build pandoc -o %pdf %md -->\n");
+        write_file(&root.join("my notes.md"), "<!-- @build pandoc -o %pdf %md -->\n");
+        write_file(&root.join("v1:draft#2.md"), "<!-- @build pandoc -o %pdf %md -->\n");
+        let entries = export::collect(std::slice::from_ref(&root), &root, false);
+        assert_eq!(export::names(&entries), vec!["docs-guide-md", "docs-guide-md-html", "docs-guide-md-2", "my-notes-md", "v1-draft-2-md"]);
+        let make = export::render_make(&entries);
+        assert!(make.contains("all: docs-guide-md docs-guide-md-2 my-notes-md v1-draft-2-md\n"));
+        assert!(make.contains("docs-guide-md-2: docs-guide.pdf\ndocs-guide.pdf: docs-guide.md\n"));
+        assert!(make.contains("my-notes-md: my\\ notes.pdf\nmy\\ notes.pdf: my\\ notes.md\n"));
+        assert!(make.contains("v1-draft-2-md: v1\\:draft\\#2.pdf\nv1\\:draft\\#2.pdf: v1\\:draft\\#2.md\n"));
+        let just = export::render_just(&entries);
+        assert!(just.contains("# docs-guide.md\ndocs-guide-md-2:\n"));
+        let ninja = export::render_ninja(&entries);
+        assert!(ninja.contains("rule docs-guide-md-2\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_export_skips_symlinked_directories() {
+        let _cfg = user_config("md: pandoc -o %pdf %md\n");
+        let root = export_docs_tree();
+        std::os::unix::fs::symlink(&root, root.join("docs").join("up")).unwrap();
+        let entries = export::collect(std::slice::from_ref(&root), &root, false);
+        assert_eq!(export::names(&entries), vec!["docs-guide-md", "docs-guide-md-html"]);
+    }
+
+    #[test]
+    fn test_export_ninja_depfiles() {
+        use export::Entry;
+        let entry = |file: &str, ty: Option<&str>, dir: &str, command: &str, outputs: &[&str]| Entry {
+            file: file.into(),
+            ty: ty.map(str::to_string),
+            dir: dir.into(),
+            command: command.into(),
+            outputs: outputs.iter().map(|o| o.to_string()).collect(),
+        };
+        let entries = vec![
+            entry("src/tool.c", None, "src", "gcc -O2 \"tool.c\" -o \"tool.out\"", &["src/tool.out"]),
+            entry("paper.tex", None, ".", "latexmk -pdf \"paper.tex\"", &["paper.pdf"]),
+            entry("paper.tex", Some("draft"), ".", "latexmk -pdf -g \"paper.tex\"", &["paper.pdf"]),
+            entry("my notes.md", None, ".", "pandoc -o \"my notes.html\" \"my notes.md\"", &["my notes.html"]),
+        ];
+        let ninja = export::render_ninja(&entries);
+        assert!(ninja.contains("rule src-tool-c\n  command = cd src && gcc -MD -MF \"tool.out.d\" -O2 \"tool.c\" -o \"tool.out\" && sed "));
+        assert!(ninja.contains("@src/\\1@"));
+        assert!(ninja.contains("  restat = 1\n  depfile = src/tool.out.d\n  deps = gcc\nbuild src/tool.out: src-tool-c src/tool.c\nbuild src-tool-c: phony src/tool.out\n"));
+        assert!(ninja.contains("command = latexmk -deps -deps-out=\"paper.pdf.d\" -pdf \"paper.tex\" && sed -e '/^#/d' -e 's@ :@:@' \"paper.pdf.d\""));
+        // Outputs already built by another edge leave this one always dirty
+        assert!(ninja.contains("build paper-tex-draft: paper-tex-draft paper.tex\n"));
+        assert!(ninja.contains("build my$ notes.html: my-notes-md my$ notes.md\n"));
+        assert!(ninja.ends_with("build all: phony src-tool-c paper-tex my-notes-md\ndefault all\n"));
+    }
+
+    #[test]
+    fn test_export_compile_commands() {
+        let _cfg = user_config("c: gcc -Wall %c -o %out\n");
+        let root = std::fs::canonicalize(tmp_dir("compile_commands")).unwrap();
+        write_file(&root.join("tools").join("cat.cpp"), "// @build clang++ -std=c++20 -Iinc %cpp -o %out && ./%out\n");
+        write_file(&root.join("tools").join("plain.c"), "int main(void) { return 0; }\n");
+        write_file(&root.join("notes.md"), "<!-- @build pandoc -o %pdf %md -->\n");
+        let entries = export::collect(std::slice::from_ref(&root), &root, true);
+        let db: serde_json::Value = serde_json::from_str(&export::render_compile_commands(&entries, &root)).unwrap();
+        let tools = root.join("tools");
+        assert_eq!(db, serde_json::json!([
+            {
+                "directory": tools,
+                "file": tools.join("cat.cpp"),
+                "arguments": ["clang++", "-std=c++20", "-Iinc", "cat.cpp", "-o", "cat.out"],
+            },
+            {
+                "directory": tools,
+                "file": tools.join("plain.c"),
+                "arguments": ["gcc", "-Wall", "plain.c", "-o", "plain.out"],
+            },
+        ]));
+    }
+
+    #[test]
+    fn test_export_compile_commands_inside_project() {
+        let _cfg = user_config("c: gcc -Wall %c -o %out\n");
+        let root = std::fs::canonicalize(tmp_dir("compile_commands_make")).unwrap();
+        write_file(&root.join("Makefile"), "all:\n\tcc -o tool src/tool.c\n");
+        write_file(&root.join("src").join("tool.c"), "int main(void) { return 0; }\n");
+        assert!(matches!(resolve(None, &root.join("src").join("tool.c")).unwrap().source, Source::Project { .. }));
+        let entries = export::collect(std::slice::from_ref(&root), &root, true);
+        let db: serde_json::Value = serde_json::from_str(&export::render_compile_commands(&entries, &root)).unwrap();
+        let src = root.join("src");
+        assert_eq!(db, serde_json::json!([
+            {
+                "directory": src,
+                "file": src.join("tool.c"),
+                "arguments": ["gcc", "-Wall", "tool.c", "-o", "tool.out"],
+            },
+        ]));
+        // Other formats still leave the file to the project's build
+        assert!(export::collect(std::slice::from_ref(&root), &root, false).iter().all(|e| e.file != "src/tool.c"));
+    }
+
+    #[test]
+    fn test_export_vscode_writes_tasks_file() {
+        let root = tmp_dir("vscode_write");
+        let path = export::write_tasks(&root, "{}\n", false).unwrap();
+        assert_eq!(path, root.join(".vscode").join("tasks.json"));
+        write_file(&path, "{ \"mine\": true }\n");
+        assert!(export::write_tasks(&root, "{}\n", false).unwrap_err().ends_with("already exists; pass --force to replace it"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{ \"mine\": true }\n");
+        export::write_tasks(&root, "{}\n", true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{}\n");
+    }
+
+    #[test]
+    fn test_export_vscode_tasks() {
+        use export::Entry;
+        let entry = |file: &str, ty: Option<&str>, dir: &str, command: &str| Entry {
+            file: file.into(),
+            ty: ty.map(str::to_string),
+            dir: dir.into(),
+            command: command.into(),
+            outputs: Vec::new(),
+        };
+        let entries = vec![
+            entry("src/tool.c", None, "src", "gcc -Wall \"tool.c\" -o \"tool.out\" && ./\"tool.out\""),
+            entry("src/tool.c", Some("test"), "src", "clang -fsanitize=address \"tool.c\" -o t && ./t"),
+            entry("lint.py", Some("lint"), ".", "uv run ruff check \"lint.py\""),
+            entry("notes.md", None, ".", "pandoc -o \"notes.pdf\" \"notes.md\""),
+        ];
+        let doc: serde_json::Value = serde_json::from_str(&export::render_vscode(&entries)).unwrap();
+        let tasks = doc["tasks"].as_array().unwrap();
+        assert_eq!(tasks.len(), 5);
+        assert_eq!(tasks[0]["label"], "src/tool.c");
+        assert_eq!(tasks[0]["options"]["cwd"], "${workspaceFolder}/src");
+        assert_eq!(tasks[0]["problemMatcher"][0]["owner"], "ruild-gcc");
+        assert_eq!(tasks[0]["problemMatcher"][0]["fileLocation"], serde_json::json!(["autoDetect", "${workspaceFolder}/src"]));
+        assert_eq!(tasks[1]["label"], "src/tool.c -test");
+        assert_eq!(tasks[1]["group"], "test");
+        assert_eq!(tasks[2]["problemMatcher"][0]["owner"], "ruild-lint");
+        assert_eq!(tasks[3]["problemMatcher"], serde_json::json!([]));
+
+        // One generic task for the file in the editor, with every type found
+        assert_eq!(tasks[4]["command"], "ruild ${input:ruildType} \"${file}\"");
+        let owners: Vec<&str> = tasks[4]["problemMatcher"].as_array().unwrap().iter().filter_map(|m| m["owner"].as_str()).collect();
+        assert_eq!(owners, vec!["ruild-gcc", "ruild-lint"]);
+        let picks: Vec<&str> = doc["inputs"][0]["options"].as_array().unwrap().iter().filter_map(|o| o["value"].as_str()).collect();
+        assert_eq!(picks, vec!["", "-lint", "-test"]);
+    }
+
+    #[test]
+    fn test_diagnostics_rewritten_relative_to_caller() {
+        use diagnostics::{relative_to, Rewriter};
+        let root = std::fs::canonicalize(tmp_dir("diagnostics")).unwrap();
+        let src = root.join("src").join("tools");
+        for name in ["foo.c", "foo.h", "main.rs", "doc.tex", "doc.md", "app.js"] {
+            write_file(&src.join(name), "\n");
+        }
+        let mut rw = Rewriter::new(&src, &root);
+        let (text, diag) = rw.line("foo.c:12:3: error: expected ';' before '}' token");
+        assert_eq!(text, "src/tools/foo.c:12:3: error: expected ';' before '}' token");
+        let diag = diag.unwrap();
+        assert_eq!((diag.line, diag.column, diag.severity.as_deref()), (12, Some(3), Some("error")));
+        assert_eq!(diag.to_json(), r#"{"column":3,"file":"src/tools/foo.c","line":12,"message":"expected ';' before '}' token","severity":"error"}"#);
+        assert_eq!(rw.line("In file included from foo.c:2:").0, "In file included from src/tools/foo.c:2:");
+        // Only existing files are locations
+        assert_eq!(rw.line("took 12:30: done"), ("took 12:30: done".to_string(), None));
+
+        // rustc names the file on the line after the message
+        assert_eq!(rw.line("error[E0308]: mismatched types").1, None);
+        let (text, diag) = rw.line("  --> main.rs:4:9");
+        assert_eq!(text, "  --> src/tools/main.rs:4:9");
+        assert_eq!(diag.map(|d| (d.severity, d.message)), Some((Some("error".into()), "mismatched types".into())));
+
+        // LaTeX with -file-line-error, pandoc
+        assert_eq!(rw.line("./doc.tex:7: Undefined control sequence.").1.map(|d| (d.file, d.line)), Some(("src/tools/doc.tex".into(), 7)));
+        let (text, diag) = rw.line("Error parsing YAML metadata at \"doc.md\" (line 3, column 1):");
+        assert_eq!(text, "Error parsing YAML metadata at \"src/tools/doc.md\" (line 3, column 1):");
+        assert_eq!(diag.map(|d| d.column), Some(Some(1)));
+
+        // eslint's stylish format prints the absolute path above its messages
+        assert_eq!(rw.line(&src.join("app.js").display().to_string()).0, "src/tools/app.js");
+        let diag = rw.line("  3:10  error  'x' is not defined  no-undef").1.unwrap();
+        assert_eq!((diag.file.as_str(), diag.line, diag.message.as_str()), ("src/tools/app.js", 3, "'x' is not defined"));
+
+        // Called from a sibling directory
+        let mut rw = Rewriter::new(&src, &root.join("docs"));
+        assert_eq!(rw.line("foo.h:1:9: warning: unused").0, "../src/tools/foo.h:1:9: warning: unused");
+        assert_eq!(relative_to(&root, &root), PathBuf::from("."));
+    }
+
+    /// A language server with `tool.c` open, and the didOpen response.
+    struct LspSession {
+        _cfg: UserConfig,
+        dir: PathBuf,
+        uri: String,
+        server: lsp::Server,
+        opened: Vec<serde_json::Value>,
+    }
+
+    impl LspSession {
+        fn open() -> Self {
+            let cfg = user_config("c: gcc -Wall %c -o %out\nc -asm: gcc -S %c -o %s\ncpp: g++ %cpp -o %out\n");
+            let dir = std::fs::canonicalize(tmp_dir("lsp")).unwrap();
+            let file = dir.join("tool.c");
+            let text = "// @build gcc %c -o %out {{outdir}}\n// @build-run gcc %c -o %out && ./%out\n// @build_x oops\n// mail me@buildhost.org\n// @build \n";
+            write_file(&file, text);
+            let uri = format!("file://{}", file.display());
+            let mut server = lsp::Server::default();
+            let opened = server.handle(&serde_json::json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": uri, "languageId": "c", "version": 1, "text": text } } }));
+            LspSession { _cfg: cfg, dir, uri, server, opened }
+        }
+
+        fn request(&mut self, method: &str, line: usize, character: usize) -> serde_json::Value {
+            let out = self.server.handle(&serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": {
+                "textDocument": { "uri": self.uri }, "position": { "line": line, "character": character } } }));
+            out[0]["result"].clone()
+        }
+
+        fn completions(&mut self, line: usize, character: usize) -> Vec<String> {
+            let result = self.request("textDocument/completion", line, character);
+            result.as_array().unwrap().iter().map(|i| i["label"].as_str().unwrap().to_string()).collect()
+        }
+    }
+
+    #[test]
+    fn test_lsp_diagnostics() {
+        let s = LspSession::open();
+        let diags = s.opened[0]["params"]["diagnostics"].as_array().unwrap();
+        let spans: Vec<(u64, u64, u64)> = diags.iter().map(|d| (d["range"]["start"]["line"].as_u64().unwrap(), d["range"]["start"]["character"].as_u64().unwrap(), d["severity"].as_u64().unwrap())).collect();
+        assert_eq!(spans, vec![(0, 25, 2), (2, 3, 1), (4, 3, 1)]);
+        assert!(diags[0]["message"].as_str().unwrap().starts_with("unknown variable {{outdir}}"));
+    }
+
+    #[test]
+    fn test_lsp_hover_and_code_lenses() {
+        let mut s = LspSession::open();
+        let hover = s.request("textDocument/hover", 1, 5);
+        assert_eq!(hover["contents"]["value"].as_str().unwrap(), format!("**build -run** runs in `{}`\n\n```sh\ngcc \"tool.c\" -o \"tool.out\" && ./\"tool.out\"\n```", s.dir.display()));
+
+        let lenses = s.request("textDocument/codeLens", 0, 0);
+        let titles: Vec<&str> = lenses.as_array().unwrap().iter().map(|l| l["command"]["title"].as_str().unwrap()).collect();
+        assert_eq!(titles, vec!["▶ build", "▶ build -run"]);
+        assert_eq!(lenses[1]["command"]["arguments"], serde_json::json!([s.uri, "run"]));
+    }
+
+    #[test]
+    fn test_lsp_completion() {
+        let mut s = LspSession::open();
+        let types = s.completions(1, 10);
+        assert!(types.contains(&"run".to_string()) && types.contains(&"asm".to_string()));
+        assert!(!types.contains(&"pp".to_string()));
+        assert_eq!(s.completions(0, 27).len(), lsp::VARIABLES.len());
+        assert_eq!(s.completions(0, 15), vec!["c", "out", "s"]);
+        assert_eq!(s.completions(4, 10), vec!["gcc -Wall %c -o %out"]);
+        assert_eq!(s.request("shutdown", 0, 0), serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_lsp_serve_completes_unsaved_buffer() {
+        let _cfg = user_config("c: gcc -Wall %c -o %out\n");
+        let dir = std::fs::canonicalize(tmp_dir("lsp_unsaved")).unwrap();
+        let uri = format!("file://{}", dir.join("draft.c").display());
+        let text = "// @build-run gcc %c -o %out && ./%out\n// @build-\n";
+        let messages = [
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            serde_json::json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": uri, "languageId": "c", "version": 1, "text": text } } }),
+            serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "textDocument/completion",
+                "params": { "textDocument": { "uri": uri }, "position": { "line": 1, "character": 11 } } }),
+            serde_json::json!({ "jsonrpc": "2.0", "id": 3, "method": "shutdown" }),
+            serde_json::json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ];
+        let input: String = messages.iter().map(|m| { let body = m.to_string(); format!("Content-Length: {}\r\n\r\n{}", body.len(), body) }).collect();
+        let mut output = Vec::new();
+        assert_eq!(lsp::serve_on(input.as_bytes(), &mut output), 0);
+
+        // Every byte written is a framed message
+        let mut rest = String::from_utf8(output).unwrap();
+        let mut replies = Vec::new();
+        while !rest.is_empty() {
+            let (header, body) = rest.split_once("\r\n\r\n").unwrap();
+            let len: usize = header.strip_prefix("Content-Length: ").unwrap().parse().unwrap();
+            replies.push(serde_json::from_str::<serde_json::Value>(&body[..len]).unwrap());
+            rest = body[len..].to_string();
+        }
+        let completion = replies.iter().find(|r| r["id"] == 2).unwrap();
+        let run = completion["result"].as_array().unwrap().iter().find(|i| i["label"] == "run").unwrap();
+        assert_eq!(run["detail"], "gcc \"draft.c\" -o \"draft.out\" && ./\"draft.out\"");
+        assert!(!dir.join("draft.c").exists());
+    }
+
+    #[test]
+    fn test_lsp_only_for_bare_lsp_argument() {
+        let args = |a: &[&str]| a.iter().map(OsString::from).collect::<Vec<_>>();
+        assert!(is_lsp_invocation(&args(&["lsp"])));
+        assert!(is_lsp_invocation(&args(&["lsp", "--stdio"])));
+        assert!(!is_lsp_invocation(&args(&["lsp", "tool.c"])));
+        assert!(!is_lsp_invocation(&args(&["-test", "lsp"])));
+        assert!(!is_lsp_invocation(&args(&["./lsp"])));
+    }
+
+    #[test]
+    fn test_lsp_variables_are_known_to_expand_vars() {
+        let d = tmp_dir("lsp_vars");
+        let all: String = lsp::VARIABLES.iter().map(|(v, _)| format!("{{{{{}}}}} ", v)).collect();
+        assert!(!expand_vars(all, &d.join("tool.c"), &d, Some("run")).contains("{{"));
+    }
+
+    #[test]
+    fn test_cargo_targets_and_commands() {
+        use project::{cargo_command, cargo_target, CargoTarget};
+        let ws = tmp_dir("cargo_ws");
+        write_file(&ws.join("Cargo.toml"), "[workspace]\nmembers = [\"crates/*\"]\nexclude = [\"crates/old\"]\n");
+        let c = ws.join("crates").join("tool");
+        write_file(&c.join("Cargo.toml"), "[package]\nname = \"tool\"\n\n[[bin]]\nname = \"special\"\npath = \"tools/special.rs\"\n");
+        for f in ["src/lib.rs", "src/util.rs", "src/main.rs", "src/bin/gen.rs", "src/bin/srv/main.rs", "src/bin/srv/net.rs",
+                  "examples/demo.rs", "tests/api.rs", "tests/common/mod.rs", "benches/speed.rs", "tools/special.rs", "build.rs"] {
+            write_file(&c.join(f), "");
+        }
+        let m: toml::Table = fs::read_to_string(c.join("Cargo.toml")).unwrap().parse().unwrap();
+        let t = |f: &str| cargo_target(&c, &m, &c.join(f));
+        assert_eq!(t("src/lib.rs"), Some(CargoTarget::Lib));
+        assert_eq!(t("src/util.rs"), Some(CargoTarget::Lib));
+        assert_eq!(t("src/main.rs"), Some(CargoTarget::Bin("tool".into())));
+        assert_eq!(t("src/bin/gen.rs"), Some(CargoTarget::Bin("gen".into())));
+        assert_eq!(t("src/bin/srv/net.rs"), Some(CargoTarget::Bin("srv".into())));
+        assert_eq!(t("examples/demo.rs"), Some(CargoTarget::Example("demo".into())));
+        assert_eq!(t("tests/api.rs"), Some(CargoTarget::Test("api".into())));
+        assert_eq!(t("tests/common/mod.rs"), Some(CargoTarget::Tests));
+        assert_eq!(t("benches/speed.rs"), Some(CargoTarget::Bench("speed".into())));
+        assert_eq!(t("tools/special.rs"), Some(CargoTarget::Bin("special".into())));
+        assert_eq!(t("build.rs"), Some(CargoTarget::BuildScript));
+
+        // Workspace members build from the workspace root with -p
+        let p = find_project_root(&c.join("src").join("bin"), Some("rs")).unwrap();
+        let cmd = |f: &str, ty: Option<&str>| cargo_command(&p, &c.join(f), ty).map(|(c, _)| c);
+        assert_eq!(cmd("src/bin/gen.rs", None).as_deref(), Some("cargo build -p tool --bin gen"));
+        assert_eq!(cmd("examples/demo.rs", Some("run")).as_deref(), Some("cargo run -p tool --example demo"));
+        assert_eq!(cmd("tests/api.rs", Some("test")).as_deref(), Some("cargo test -p tool --test api"));
+        assert_eq!(cmd("src/util.rs", Some("clippy")).as_deref(), Some("cargo clippy -p tool --lib"));
+        assert_eq!(cmd("src/util.rs", Some("fuzz")), None);
+        assert_eq!(cargo_command(&p, &c.join("src/lib.rs"), None).unwrap().1, ws);
+
+        // An excluded crate stands alone
+        let old = ws.join("crates").join("old");
+        write_file(&old.join("Cargo.toml"), "[package]\nname = \"old\"\n");
+        write_file(&old.join("src").join("main.rs"), "");
+        let p = find_project_root(&old.join("src"), Some("rs")).unwrap();
+        assert_eq!(
+            cargo_command(&p, &old.join("src").join("main.rs"), Some("run")),
+            Some(("cargo run --bin old".to_string(), old.clone()))
+        );
+    }
+
+    #[test]
+    fn test_package_manager_detection_in_monorepos() {
+        let root = tmp_dir("pm_mono");
+        fs::create_dir_all(root.join(".git")).unwrap();
+        write_file(&root.join("pnpm-workspace.yaml"), "packages: ['packages/*']\n");
+        write_file(&root.join("pnpm-lock.yaml"), "lockfileVersion: 9\n");
+        let pkg = root.join("packages").join("web");
+        write_file(&pkg.join("package.json"), "{\"name\": \"web\"}\n");
+        assert_eq!(pick_package_manager(&pkg.join("src")), PackageManager::Pnpm);
+
+        // packageManager beats lockfiles at the same level
+        write_file(&root.join("package.json"), "{\"packageManager\": \"yarn@4.1.0\"}\n");
+        assert_eq!(pick_package_manager(&pkg), PackageManager::Yarn);
+        // ...and a nearer lockfile beats the workspace root
+        write_file(&pkg.join("bun.lock"), "{}\n");
+        assert_eq!(pick_package_manager(&pkg), PackageManager::Bun);
+
+        let deno = tmp_dir("pm_deno");
+        fs::create_dir_all(deno.join(".git")).unwrap();
+        write_file(&deno.join("deno.json"), "{}\n");
+        assert_eq!(pick_package_manager(&deno), PackageManager::Deno);
+        let cmd = expand_vars("{{pm_run}} build && {{pm_test}} && {{pm}}".into(), &deno.join("main.ts"), &deno, None);
+        assert_eq!(cmd, "deno task build && deno test && deno");
+
+        // The walk stops at the repository root
+        let inner = deno.join("sub");
+        fs::create_dir_all(inner.join(".git")).unwrap();
+        assert_eq!(pick_package_manager(&inner), PackageManager::Npm);
+    }
+
+    #[test]
+    fn test_go_package_commands() {
+        let m = tmp_dir("go_mod");
+        write_file(&m.join("go.mod"), "module example.com/m\n\ngo 1.22\n");
+        write_file(&m.join("lib.go"), "// Package m does things.\npackage m\n");
+        write_file(&m.join("internal/store/store.go"), "package store\n");
+        write_file(&m.join("internal/store/store_test.go"), "package store_test\n");
+        write_file(&m.join("cmd/tool/main.go"), "package main\n\nfunc main() {}\n");
+        let m = fs::canonicalize(&m).unwrap();
+        let p = find_project_root(&m.join("cmd"), Some("go")).unwrap();
+        let cmd = |f: &str, ty: Option<&str>| project_command(&p, &m.join(f), ty);
+
+        assert_eq!(cmd("lib.go", None), Some(("go build .".to_string(), m.clone())));
+        assert_eq!(cmd("internal/store/store.go", None), Some(("go build ./internal/store".to_string(), m.clone())));
+        assert_eq!(cmd("internal/store/store.go", Some("test")), Some(("go test ./internal/store".to_string(), m.clone())));
+        assert_eq!(cmd("internal/store/store_test.go", None).map(|c| c.0).as_deref(), Some("go test ./internal/store"));
+        assert_eq!(cmd("internal/store/store.go", Some("run")), None);
+        // package main runs from its own directory
+        assert_eq!(cmd("cmd/tool/main.go", None), Some(("go run .".to_string(), m.join("cmd/tool"))));
+        assert_eq!(cmd("cmd/tool/main.go", Some("build")).map(|c| c.0).as_deref(), Some("go build ./cmd/tool"));
+
+        let file = m.join("cmd/tool/main.go");
+        let vars = expand_vars("{{go_root}} {{go_pkg}}".into(), &file, &m, None);
+        assert_eq!(vars, format!("\"{}\" ./cmd/tool", m.display()));
+    }
+
+    #[test]
+    fn test_python_runner_detection() {
+        use project::PyRunner;
+        let root = tmp_dir("py_runner");
+        fs::create_dir_all(root.join(".git")).unwrap();
+        let pkg = root.join("src").join("app");
+        fs::create_dir_all(&pkg).unwrap();
+        assert_eq!(pick_python_runner(&pkg), PyRunner::System);
+
+        // A local virtualenv, then pyproject tool sections, then lockfiles
+        let venv_py = if cfg!(windows) { root.join(".venv/Scripts/python.exe") } else { root.join(".venv/bin/python") };
+        write_file(&venv_py, "");
+        assert_eq!(pick_python_runner(&pkg), PyRunner::Venv(venv_py.clone()));
+        write_file(&root.join("pyproject.toml"), "[project]\nname = \"app\"\n\n[tool.poetry]\nname = \"app\"\n");
+        assert_eq!(pick_python_runner(&pkg), PyRunner::Poetry);
+        write_file(&root.join("uv.lock"), "version = 1\n");
+        assert_eq!(pick_python_runner(&pkg), PyRunner::Uv);
+
+        let main = pkg.join("main.py");
+        write_file(&main, "print('hi')\n");
+        let cmd = expand_vars("{{py}} {{file}} && {{py_run}} pytest".into(), &main, &pkg, None);
+        assert_eq!(cmd, "uv run python \"main.py\" && uv run pytest");
+
+        // The project layer runs single files in place and packages on -build
+        let p = find_project_root(&pkg, Some("py")).unwrap();
+        let canon = fs::canonicalize(&main).unwrap();
+        let (run, dir) = project_command(&p, &canon, None).unwrap();
+        assert_eq!(run, "uv run python \"main.py\"");
+        assert_eq!(dir, canon.parent().unwrap());
+        assert_eq!(project_command(&p, &canon, Some("run")).map(|(c, _)| c), Some(run));
+        assert_eq!(project_command(&p, &canon, Some("build")), Some(("uv build".to_string(), root.clone())));
+
+        // Without a project tool, the venv interpreter runs modules
+        let plain = tmp_dir("py_venv");
+        fs::create_dir_all(plain.join(".git")).unwrap();
+        let py = if cfg!(windows) { plain.join("venv/Scripts/python.exe") } else { plain.join("venv/bin/python") };
+        write_file(&py, "");
+        assert_eq!(pick_python_runner(&plain).run_prefix(), format!("\"{}\" -m", py.display()));
+    }
+
+    #[test]
+    fn test_package_json_scripts_become_types() {
+        let d = tmp_dir("pm_scripts");
+        fs::create_dir_all(d.join(".git")).unwrap();
+        write_file(&d.join("pnpm-lock.yaml"), "lockfileVersion: 9\n");
+        let file = d.join("package.json");
+        write_file(&file, "{\"scripts\": {\"typecheck\": \"tsc --noEmit\", \"build:prod\": \"vite build\", \"test\": \"vitest\"}}\n");
+
+        assert_eq!(project_command_for_file(Some("typecheck"), &file).as_deref(), Some("pnpm run typecheck"));
+        assert_eq!(project_command_for_file(Some("build:prod"), &file).as_deref(), Some("pnpm run build:prod"));
+        assert_eq!(project_command_for_file(Some("install"), &file).as_deref(), Some("pnpm install"));
+        // Missing scripts are not handed to the package manager
+        assert_eq!(project_command_for_file(Some("lint"), &file), None);
+        assert_eq!(project_command_for_file(None, &file), None);
+
+        let types = builtin_types(&file, &d);
+        assert_eq!(types, vec!["build:prod", "test", "typecheck", "install"]);
+        assert_eq!(suggest_type("typechek", &types), Some("typecheck"));
+    }
+
+    #[test]
+    fn test_package_scripts_named_like_pm_commands_run_the_script() {
+        let d = tmp_dir("pm_clash");
+        fs::create_dir_all(d.join(".git")).unwrap();
+        let file = d.join("package.json");
+        write_file(&file, "{\"scripts\": {\"publish\": \"./release.sh\", \"deploy\": \"./deploy.sh\"}}\n");
+        write_file(&d.join("pnpm-lock.yaml"), "lockfileVersion: 9\n");
+        assert_eq!(project_command_for_file(Some("publish"), &file).as_deref(), Some("pnpm run publish"));
+        fs::remove_file(d.join("pnpm-lock.yaml")).unwrap();
+        write_file(&d.join("yarn.lock"), "# lock\n");
+        assert_eq!(project_command_for_file(Some("deploy"), &file).as_deref(), Some("yarn run deploy"));
+    }
+
+    /// The package.json rules bundled before the built-in layer read scripts,
+    /// as they sit in configs bootstrapped back then.
+    const OLD_PACKAGE_JSON_RULES: &str = "\
+file:package.json: {{pm}} run build
+file:package.json -build: {{pm}} run build
+file:package.json -start: {{pm_start}}
+file:package.json -test: {{pm_test}}
+file:package.json -install: {{pm_install}}
+file:package.json -lint: {{pm}} run lint
+file:package.json -format: {{pm}} run format
+file:package.json -fmt: {{pm}} run format
+file:package.json -dev: {{pm}} run dev
+file:package.json -clean: {{pm}} run clean
+";
+
+    /// The compose.yml rules from the same old bundled defaults.
+    const OLD_COMPOSE_RULES: &str = "\
+file:compose.yml: docker compose up -d
+file:compose.yml -down: docker compose down
+file:compose.yml -build: docker compose build
+file:compose.yml -pull: docker compose pull
+file:compose.yml -logs: docker compose logs -f
+file:compose.yml -ps: docker compose ps
+file:compose.yml -stop: docker compose stop
+file:compose.yml -start: docker compose start
+file:compose.yml -restart: docker compose restart
+file:compose.yml -recreate: docker compose up -d --force-recreate
+file:compose.yml -prune: docker compose down --volumes --remove-orphans
+";
+
+    #[test]
+    fn test_old_bundled_compose_rules_leave_service_types_to_builtin_layer() {
+        let cfg = user_config(&format!("{}{}", OLD_COMPOSE_RULES, OLD_PACKAGE_JSON_RULES));
+        let file = tmp_dir("compose_old_rules").join("compose.yml");
+        write_file(&file, "services:\n  api:\n    image: api\n");
+
+        let r = resolve(Some("logs:api"), &file).unwrap();
+        assert_eq!(r.tpl, "docker compose logs -f api");
+        assert!(matches!(r.source, Source::Builtin { .. }));
+        assert!(resolve(Some("logs:web"), &file).is_none());
+        assert!(matches!(resolve(None, &file).unwrap().source, Source::Builtin { .. }));
+
+        // An edited rule is the user's own and still comes first
+        write_file(&cfg.dir.join("build.defaults"), "file:compose.yml: docker compose up -d --wait\n");
+        assert_eq!(resolve(None, &file).unwrap().tpl, "docker compose up -d --wait");
+        assert!(resolve(Some("logs:web"), &file).is_none());
+    }
+
+    #[test]
+    fn test_old_bundled_package_json_rules_are_superseded() {
+        let _cfg = user_config(OLD_PACKAGE_JSON_RULES);
+        let d = tmp_dir("pm_old_rules");
+        fs::create_dir_all(d.join(".git")).unwrap();
+        write_file(&d.join("pnpm-lock.yaml"), "lockfileVersion: 9\n");
+        let file = d.join("package.json");
+        write_file(&file, "{\"scripts\": {\"typecheck\": \"tsc --noEmit\", \"build\": \"vite build\"}}\n");
+
+        let r = resolve(Some("typecheck"), &file).unwrap();
+        assert_eq!(r.tpl, "pnpm run typecheck");
+        assert!(matches!(r.source, Source::Builtin { .. }));
+        assert_eq!(resolve(None, &file).unwrap().tpl, "pnpm run build");
+        assert!(resolve(Some("lint"), &file).is_none());
+        assert_eq!(unknown_type_hint("lint", &file), Some(format!("{}: no `lint` script; known types: -build -install -typecheck", file.display())));
+        // Each skipped rule was reported, naming where it is
+        let skipped = format!(
+            "{}:6: skipping `file:package.json -lint`, a rule from older bundled defaults; the built-in layer handles it (`builtin off` keeps the rule)",
+            _cfg.dir.join("build.defaults").display()
+        );
+        assert!(!report_once(skipped));
+    }
+
+    #[test]
+    fn test_typed_request_skips_untyped_file_rules() {
+        let _cfg = user_config("file:VERSION: echo bump\nfile:VERSION -tag: git tag v1\n");
+        let file = tmp_dir("untyped_rule").join("VERSION");
+        write_file(&file, "1.0\n");
+        assert_eq!(resolve(None, &file).unwrap().tpl, "echo bump");
+        assert_eq!(resolve(Some("build"), &file).unwrap().tpl, "echo bump");
+        assert_eq!(resolve(Some("tag"), &file).unwrap().tpl, "git tag v1");
+        assert!(resolve(Some("release"), &file).is_none());
+    }
+
+    #[test]
+    fn test_parse_compose_services_and_profiles() {
+        let yaml = "name: demo\nservices:\n  api:\n    image: api\n    profiles: [\"web\", debug]\n  db:\n    image: postgres\n    profiles:\n      - data\n  # cache: disabled\n  'worker': {image: w}\nvolumes:\n  pg:\n";
+        let info = parse_compose(yaml);
+        assert_eq!(info.services, vec!["api", "db", "worker"]);
+        assert_eq!(info.profiles, vec!["data", "debug", "web"]);
+
+        assert_eq!(compose_target_cmd("logs", "api", &info).as_deref(), Some("docker compose logs -f api"));
+        assert_eq!(compose_target_cmd("restart", "db", &info).as_deref(), Some("docker compose restart db"));
+        assert_eq!(compose_target_cmd("up", "debug", &info).as_deref(), Some("docker compose --profile debug up -d"));
+        assert_eq!(compose_target_cmd("logs", "nope", &info), None);
+        assert_eq!(compose_target_cmd("frob", "api", &info), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_compose_service_type_runs_docker() {
+        use std::os::unix::fs::PermissionsExt;
+        let _cfg = user_config("# empty\n");
+        let bin = tmp_dir("compose_bin");
+        let docker = bin.join("docker");
+        write_file(&docker, "#!/bin/sh\necho \"$@\" > docker_args\n");
+        fs::set_permissions(&docker, fs::Permissions::from_mode(0o755)).unwrap();
+        let mut paths = vec![bin.clone()];
+        paths.extend(env::split_paths(&env::var_os("PATH").unwrap_or_default()));
+        let _path = EnvVar::set("PATH", env::join_paths(paths).unwrap());
+
+        let d = tmp_dir("compose_proj");
+        let file = d.join("compose.yml");
+        write_file(&file, "services:\n  api:\n    image: api\n  db:\n    image: db\n");
+        assert!(build_file(Some("logs:api"), &file));
+        assert_eq!(fs::read_to_string(d.join("docker_args")).unwrap().trim(), "compose logs -f api");
+        assert!(!build_file(Some("logs:web"), &file));
+    }
+
     #[test]
     fn test_bootstrap_defaults_created_and_used() {
         let _guard = ENV_GUARD.lock().unwrap();
@@ -777,9 +3369,10 @@ mod tests {
         let old_xdg = env::var_os("XDG_CONFIG_HOME");
         unsafe { env::set_var("XDG_CONFIG_HOME", &cfgdir); }
 
-        // File does not exist initially; read_defaults should bootstrap it
-        let got = read_defaults("txt");
-        assert_eq!(got.as_deref(), Some("pandoc -o %pdf %txt"));
+        // File does not exist initially; loading the config should bootstrap it
+        let cfg = load_defaults_cfg(None).unwrap();
+        let got = cfg.ext_map.get("txt").map(|r| r.cmd.as_str());
+        assert_eq!(got, Some("pandoc -o %pdf %txt"));
         assert!(cfgfile.exists());
 
         // restore
@@ -828,18 +3421,18 @@ mod tests {
 
         // package.json mapping with PM detection
         let d = tmp_dir("pm_detect");
-        write_file(&d.join("package.json"), "{\n}\n");
+        write_file(&d.join("package.json"), "{\"scripts\": {\"build\": \"tsc\", \"start\": \"node .\"}}\n");
         write_file(&d.join("yarn.lock"), "# lock\n");
         let file = d.join("package.json");
         assert_eq!(
             project_command_for_file(Some("build"), &file).as_deref(),
-            Some("yarn build")
+            Some("yarn run build")
         );
         // prefer pnpm if lock exists
         write_file(&d.join("pnpm-lock.yaml"), "lockfileVersion: 9\n");
         assert_eq!(
             project_command_for_file(Some("start"), &file).as_deref(),
-            Some("pnpm start")
+            Some("pnpm run start")
         );
     }
 }
@@ -0,0 +1,83 @@
+// SPDX-License-Identifier: MIT
+//! Makefiles: their targets are types, `--list` shows `##` help comments.
+
+use std::path::Path;
+
+use crate::normalize_type;
+
+/// A target declared by a makefile rule.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MakeTarget {
+    pub name: String,
+    /// `## help` text, after the rule or on the line above it
+    pub help: Option<String>,
+}
+
+/// `Makefile`, `makefile`, `GNUmakefile` or `*.mk` (`name` lowercased).
+pub fn is_makefile(name: &str) -> bool {
+    matches!(name, "makefile" | "gnumakefile") || name.ends_with(".mk")
+}
+
+/// Explicit targets of a makefile, in order of first appearance. Special
+/// targets (`.PHONY`), pattern rules and targets built from variables are
+/// left out, as are recipe lines, `define` blocks and variable assignments.
+pub fn make_targets(text: &str) -> Vec<MakeTarget> {
+    let mut targets: Vec<MakeTarget> = Vec::new();
+    let mut pending_help: Option<String> = None;
+    let mut in_define = false;
+    for line in text.lines() {
+        let t = line.trim();
+        if in_define {
+            in_define = t != "endef";
+            continue;
+        }
+        if line.starts_with([' ', '\t']) || t.is_empty() {
+            pending_help = None;
+            continue;
+        }
+        if let Some(help) = t.strip_prefix("##") {
+            pending_help = Some(help.trim().to_string()).filter(|h| !h.is_empty());
+            continue;
+        }
+        if t.starts_with("define ") || t == "define" {
+            in_define = true;
+            continue;
+        }
+        let help = pending_help.take();
+        let Some(colon) = t.find(':') else { continue };
+        let (names, rest) = (&t[..colon], t[colon + 1..].trim_start_matches(':'));
+        let (deps, trailing_help) = match rest.split_once("##") {
+            Some((deps, h)) => (deps, Some(h.trim().to_string()).filter(|h| !h.is_empty())),
+            None => (rest, None),
+        };
+        if names.contains(['=', '#']) || rest.starts_with('=') || deps.contains('=') {
+            continue;
+        }
+        let help = trailing_help.or(help);
+        for name in names.split_whitespace() {
+            if name.starts_with('.') || name.contains(['$', '%']) {
+                continue;
+            }
+            match targets.iter_mut().find(|t| t.name == name) {
+                Some(known) => known.help = known.help.take().or_else(|| help.clone()),
+                None => targets.push(MakeTarget { name: name.to_string(), help: help.clone() }),
+            }
+        }
+    }
+    targets
+}
+
+/// `make` command for a target of the makefile at `path`, run from its
+/// directory; `-f` is added unless make would pick that file by itself.
+/// `None` when the type names no target.
+pub fn make_command(path: &Path, ty: Option<&str>) -> Option<String> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let name = path.file_name()?.to_string_lossy().to_string();
+    let default = ["GNUmakefile", "makefile", "Makefile"].into_iter().find(|n| dir.join(n).is_file());
+    let make = if default == Some(name.as_str()) { "make".to_string() } else { format!("make -f \"{}\"", name) };
+    let Some(ty) = ty else { return Some(make) };
+    let want = normalize_type(ty);
+    let text = std::fs::read_to_string(path).ok()?;
+    let target = make_targets(&text).into_iter().find(|t| normalize_type(&t.name) == want)?;
+    Some(format!("{} {}", make, target.name))
+}
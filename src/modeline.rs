@@ -0,0 +1,138 @@
+// SPDX-License-Identifier: MIT
+//! Editor-native compile settings: Emacs `compile-command` file variables and
+//! Vim `makeprg` modelines, read when the config says `modelines on`.
+
+use regex::Regex;
+
+/// A compile command an editor setting declares for a file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EditorRecipe {
+    pub cmd: String,
+    /// 1-based line of the setting
+    pub line: usize,
+    /// Which setting it came from, for `--explain`
+    pub kind: &'static str,
+}
+
+/// Lines a Vim modeline may sit on, at each end of the file (Vim's default).
+const MODELINES: usize = 5;
+
+/// The first editor recipe of `text`: the Emacs `-*- ... -*-` header, then a
+/// `Local Variables:` block, then a Vim modeline.
+pub fn editor_recipe(text: &str) -> Option<EditorRecipe> {
+    let lines: Vec<&str> = text.lines().collect();
+    emacs_header(&lines).or_else(|| emacs_local_variables(&lines)).or_else(|| vim_modeline(&lines))
+}
+
+/// `-*- mode: c; compile-command: "make -k" -*-` on the first line, or on the
+/// second after a `#!` line.
+fn emacs_header(lines: &[&str]) -> Option<EditorRecipe> {
+    let n = if lines.first().is_some_and(|l| l.starts_with("#!")) { 2 } else { 1 };
+    lines.iter().take(n).enumerate().find_map(|(i, l)| {
+        let start = l.find("-*-")? + 3;
+        let end = start + l[start..].find("-*-")?;
+        let cmd = file_variable(&l[start..end], "compile-command")?;
+        Some(EditorRecipe { cmd, line: i + 1, kind: "Emacs compile-command" })
+    })
+}
+
+/// `compile-command: "..."` inside a `Local Variables:` ... `End:` block near
+/// the end of the file. Each line carries the same prefix and suffix (usually
+/// the comment markers), which are stripped.
+fn emacs_local_variables(lines: &[&str]) -> Option<EditorRecipe> {
+    let start = lines.iter().rposition(|l| l.contains("Local Variables:"))?;
+    let head = lines[start];
+    let at = head.find("Local Variables:")?;
+    let (prefix, suffix) = (&head[..at], head[at + "Local Variables:".len()..].trim());
+    for (i, l) in lines.iter().enumerate().skip(start + 1) {
+        let body = l.strip_prefix(prefix).unwrap_or(l);
+        let body = body.trim_end().strip_suffix(suffix).unwrap_or(body).trim();
+        if body == "End:" {
+            break;
+        }
+        if let Some(cmd) = file_variable(body, "compile-command") {
+            return Some(EditorRecipe { cmd, line: i + 1, kind: "Emacs compile-command" });
+        }
+    }
+    None
+}
+
+/// Value of the string variable `name` in `var: value; var: value` text.
+/// Non-string values (Lisp expressions) are not evaluated and yield `None`.
+fn file_variable(text: &str, name: &str) -> Option<String> {
+    let re = Regex::new(&format!(r#"(?:^|;)\s*{}\s*:\s*"((?:[^"\\]|\\.)*)""#, regex::escape(name))).unwrap();
+    let raw = re.captures(text)?.get(1)?.as_str().to_string();
+    let mut out = String::new();
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some(o) => out.push(o),
+            None => {}
+        }
+    }
+    let cmd = out.trim().to_string();
+    (!cmd.is_empty()).then_some(cmd)
+}
+
+/// `vim: set makeprg=gcc\ -Wall\ %:` (or `vim: makeprg=...`) in the first or
+/// last lines of the file. Vim's `%` and `%<`/`%:r` become `{{file}}` and
+/// `{{file_stem}}`.
+fn vim_modeline(lines: &[&str]) -> Option<EditorRecipe> {
+    let re = Regex::new(r#"(?:^|\s)(?:vi|vim|Vim|ex):\s*(set?\s+)?(.*)$"#).unwrap();
+    let tail_start = lines.len().saturating_sub(MODELINES).max(MODELINES.min(lines.len()));
+    let candidates = (0..MODELINES.min(lines.len())).chain(tail_start..lines.len());
+    for i in candidates {
+        let Some(c) = re.captures(lines[i]) else { continue };
+        let set_form = c.get(1).is_some();
+        for opt in vim_options(&c[2], set_form) {
+            if let Some(v) = opt.strip_prefix("makeprg=").or_else(|| opt.strip_prefix("mp=")) {
+                if !v.is_empty() {
+                    return Some(EditorRecipe { cmd: vim_to_template(v), line: i + 1, kind: "Vim makeprg" });
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Split modeline options. The `set` form ends at the first unescaped `:`
+/// and separates options by spaces; the bare form also separates by `:`.
+/// A backslash escapes the next space or colon.
+fn vim_options(text: &str, set_form: bool) -> Vec<String> {
+    let mut opts = Vec::new();
+    let mut cur = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some(' ' | ':' | '\\')) => cur.push(chars.next().unwrap_or(c)),
+            ':' if set_form => break,
+            ' ' | '\t' | ':' => {
+                if !cur.is_empty() {
+                    opts.push(std::mem::take(&mut cur));
+                }
+            }
+            _ => cur.push(c),
+        }
+    }
+    if !cur.is_empty() {
+        opts.push(cur);
+    }
+    opts
+}
+
+/// Translate Vim's filename modifiers into ruild variables.
+fn vim_to_template(makeprg: &str) -> String {
+    let re = Regex::new(r#"\\%|%(?::r|<)|%"#).unwrap();
+    re.replace_all(makeprg, |c: &regex::Captures| match &c[0] {
+        "%" => "{{file}}",
+        "\\%" => "%",
+        _ => "{{file_stem}}",
+    })
+    .to_string()
+}
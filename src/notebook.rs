@@ -0,0 +1,94 @@
+// SPDX-License-Identifier: MIT
+//! Jupyter notebooks: directives live in cell sources, where `detect` would
+//! otherwise only see JSON strings, and in `ruild` notebook or cell metadata.
+
+use serde_json::Value;
+
+use crate::{scan_lines, Directive, FileScan};
+
+/// Directives of a notebook: the notebook's `ruild` metadata first, then each
+/// cell's metadata and source, in order. Line numbers point into the `.ipynb`
+/// file itself. Text that isn't a notebook is scanned line by line.
+pub fn scan_notebook(text: &str) -> FileScan {
+    let Ok(nb) = serde_json::from_str::<Value>(text) else {
+        return scan_lines(text.lines().map(|l| Ok(l.to_string())));
+    };
+    let raw: Vec<&str> = text.lines().collect();
+    let mut scan = FileScan::default();
+    metadata_directives(nb.get("metadata"), &raw, &mut scan);
+
+    let mut cursor = 0;
+    for cell in nb.get("cells").and_then(Value::as_array).into_iter().flatten() {
+        metadata_directives(cell.get("metadata"), &raw, &mut scan);
+        let source = cell_source(cell);
+        let lines: Vec<&str> = source.lines().collect();
+        let cell_scan = scan_lines(source.lines().map(|l| Ok(l.to_string())));
+        for mut d in cell_scan.directives {
+            d.line = locate(&raw, &mut cursor, lines[d.line - 1]);
+            scan.directives.push(d);
+        }
+        if scan.target.is_none() {
+            scan.target = cell_scan.target.map(|(path, line)| (path, locate(&raw, &mut cursor, lines[line - 1])));
+        }
+    }
+    scan
+}
+
+/// A cell's source, stored either as one string or as a list of lines.
+fn cell_source(cell: &Value) -> String {
+    match cell.get("source") {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(parts)) => parts.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    }
+}
+
+/// Directives from a `ruild` metadata entry: a command string for a plain
+/// build, or an object keyed `build` / `build-<type>` whose values are a
+/// command or a list of commands run in sequence.
+fn metadata_directives(metadata: Option<&Value>, raw: &[&str], scan: &mut FileScan) {
+    let Some(entry) = metadata.and_then(|m| m.get("ruild")) else { return };
+    let mut push = |ty: &str, value: &Value| {
+        let cmds: Vec<&str> = match value {
+            Value::String(s) => vec![s.trim()],
+            Value::Array(cmds) => cmds.iter().filter_map(Value::as_str).map(str::trim).collect(),
+            _ => return,
+        };
+        let tpl = cmds.join(" && ");
+        if tpl.is_empty() {
+            return;
+        }
+        let line = locate(raw, &mut 0, cmds[0]);
+        scan.directives.push(Directive { ty: ty.to_string(), tpl, line, sidecar: None });
+    };
+    match entry {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let ty = match key.strip_prefix("build") {
+                    Some("") => "",
+                    Some(rest) => match rest.strip_prefix('-') {
+                        Some(t) if !t.is_empty() => t,
+                        _ => continue,
+                    },
+                    None => continue,
+                };
+                push(ty, value);
+            }
+        }
+        other => push("", other),
+    }
+}
+
+/// 1-based line of the `.ipynb` file holding `text` (JSON-escaped), searching
+/// from `cursor` on and moving it past the hit. Falls back to line 1.
+fn locate(raw: &[&str], cursor: &mut usize, text: &str) -> usize {
+    let encoded = serde_json::to_string(text.trim()).unwrap_or_default();
+    let needle = encoded.trim_matches('"');
+    match raw.iter().skip(*cursor).position(|l| l.contains(needle)) {
+        Some(i) => {
+            *cursor += i + 1;
+            *cursor
+        }
+        None => 1,
+    }
+}
@@ -0,0 +1,445 @@
+// SPDX-License-Identifier: MIT
+//! Project root discovery: build the enclosing project from any of its source files.
+
+use std::path::{Path, PathBuf};
+
+use crate::{normalize_type, package_scripts};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ProjectKind { Cargo, Go, Python, Node, CMake, Meson, Make, Gradle, Maven }
+
+/// Marker files in the order they are checked within one directory, with the
+/// source extensions each kind of project claims.
+const PROJECT_MARKERS: &[(&str, ProjectKind, &[&str])] = &[
+    ("Cargo.toml", ProjectKind::Cargo, &["rs"]),
+    ("go.mod", ProjectKind::Go, &["go"]),
+    ("pyproject.toml", ProjectKind::Python, &["py", "pyi"]),
+    ("package.json", ProjectKind::Node, &["js", "mjs", "cjs", "jsx", "ts", "mts", "cts", "tsx", "vue", "svelte"]),
+    ("CMakeLists.txt", ProjectKind::CMake, C_FAMILY),
+    ("meson.build", ProjectKind::Meson, C_FAMILY),
+    ("Makefile", ProjectKind::Make, C_FAMILY),
+    ("build.gradle", ProjectKind::Gradle, JVM),
+    ("build.gradle.kts", ProjectKind::Gradle, JVM),
+    ("pom.xml", ProjectKind::Maven, JVM),
+];
+
+const C_FAMILY: &[&str] = &["c", "h", "cc", "cpp", "cxx", "c++", "hh", "hpp", "hxx", "m", "mm", "s"];
+const JVM: &[&str] = &["java", "kt", "kts", "groovy", "scala"];
+
+/// A project found above a file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProjectRoot {
+    pub kind: ProjectKind,
+    pub root: PathBuf,
+    /// The marker file that identified the project
+    pub marker: PathBuf,
+}
+
+/// Nearest project at or above `start`. With `ext`, only project kinds that
+/// claim that source extension are considered, so a Markdown file inside a
+/// Cargo crate keeps its own recipe. The walk stops at the repository root
+/// (`.git`), so a stray Makefile further up does not claim the files.
+pub fn find_project_root(start: &Path, ext: Option<&str>) -> Option<ProjectRoot> {
+    let ext = ext.map(str::to_ascii_lowercase);
+    for dir in start.ancestors() {
+        for (marker, kind, exts) in PROJECT_MARKERS {
+            if let Some(e) = &ext {
+                if !exts.contains(&e.as_str()) {
+                    continue;
+                }
+            }
+            let path = dir.join(marker);
+            if path.is_file() {
+                return Some(ProjectRoot { kind: *kind, root: dir.to_path_buf(), marker: path });
+            }
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+    }
+    None
+}
+
+/// Whole-project command for a type, run from the project root. `None` when the
+/// project kind has no recipe for that type.
+pub fn project_root_command(p: &ProjectRoot, ty: Option<&str>) -> Option<String> {
+    let t = ty.map(normalize_type);
+    let test = match t.as_deref() {
+        None | Some("build") => false,
+        Some("test") => true,
+        Some(_) => return None,
+    };
+    let has = |name: &str| p.root.join(name).exists();
+    let cmd = match p.kind {
+        ProjectKind::Cargo => if test { "cargo test" } else { "cargo build" }.to_string(),
+        ProjectKind::Go => if test { "go test ./..." } else { "go build ./..." }.to_string(),
+        ProjectKind::Python => {
+            let runner = pick_python_runner(&p.root);
+            if test { format!("{} pytest -q", runner.run_prefix()) } else { runner.build() }
+        }
+        ProjectKind::Node => {
+            // Like package.json itself: a readable scripts list must have the script
+            let script = if test { "test" } else { "build" };
+            if package_scripts(&p.root.join("package.json")).is_some_and(|s| !s.iter().any(|n| n == script)) {
+                return None;
+            }
+            if test { "{{pm_test}}" } else { "{{pm_run}} build" }.to_string()
+        }
+        ProjectKind::CMake => {
+            let configure = if has("build/CMakeCache.txt") { "" } else { "cmake -S . -B build && " };
+            let action = if test { "ctest --test-dir build" } else { "cmake --build build" };
+            format!("{}{}", configure, action)
+        }
+        ProjectKind::Meson => {
+            let setup = if has("builddir") { "" } else { "meson setup builddir && " };
+            let action = if test { "meson test -C builddir" } else { "meson compile -C builddir" };
+            format!("{}{}", setup, action)
+        }
+        ProjectKind::Make => if test { "make test" } else { "make" }.to_string(),
+        ProjectKind::Gradle => {
+            let gradle = if has("gradlew") { "./gradlew" } else { "gradle" };
+            format!("{} {}", gradle, if test { "test" } else { "build" })
+        }
+        ProjectKind::Maven => if test { "mvn test" } else { "mvn package" }.to_string(),
+    };
+    Some(cmd)
+}
+
+/// Types `project_command` understands for a project.
+pub fn project_types(p: &ProjectRoot) -> &'static [&'static str] {
+    match p.kind {
+        ProjectKind::Cargo => &["build", "check", "clippy", "doc", "run", "test", "bench"],
+        ProjectKind::Go => &["build", "run", "test", "vet"],
+        ProjectKind::Python => &["build", "run", "test"],
+        _ => &["build", "test"],
+    }
+}
+
+/// Command and working directory for `file` (an absolute path) inside project `p`.
+pub fn project_command(p: &ProjectRoot, file: &Path, ty: Option<&str>) -> Option<(String, PathBuf)> {
+    match p.kind {
+        ProjectKind::Cargo => cargo_command(p, file, ty),
+        ProjectKind::Go => go_command(p, file, ty),
+        ProjectKind::Python => python_command(p, file, ty),
+        _ => project_root_command(p, ty).map(|c| (c, p.root.clone())),
+    }
+}
+
+// ── Cargo ───────────────────────────────────────────────────────────────────
+
+/// The Cargo target a source file belongs to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CargoTarget {
+    Lib,
+    Bin(String),
+    Example(String),
+    Test(String),
+    /// A helper module shared by integration tests (e.g. `tests/common/mod.rs`)
+    Tests,
+    Bench(String),
+    BuildScript,
+}
+
+impl CargoTarget {
+    /// Target selection flags for `cargo build`/`test`/`clippy`.
+    fn selector(&self) -> String {
+        match self {
+            CargoTarget::Lib => "--lib".into(),
+            CargoTarget::Bin(n) => format!("--bin {}", n),
+            CargoTarget::Example(n) => format!("--example {}", n),
+            CargoTarget::Test(n) => format!("--test {}", n),
+            CargoTarget::Tests => "--tests".into(),
+            CargoTarget::Bench(n) => format!("--bench {}", n),
+            CargoTarget::BuildScript => String::new(),
+        }
+    }
+}
+
+fn read_manifest(path: &Path) -> Option<toml::Table> {
+    std::fs::read_to_string(path).ok()?.parse().ok()
+}
+
+/// `a/b/c` form of `path` relative to `base`, for comparing with manifest paths.
+fn rel_slash(path: &Path, base: &Path) -> Option<String> {
+    let rel = path.strip_prefix(base).ok()?;
+    Some(rel.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/"))
+}
+
+/// Which target of the package rooted at `crate_root` compiles `file`: explicit
+/// `[lib]`/`[[bin]]`/... paths first, then Cargo's auto-discovery layout.
+pub fn cargo_target(crate_root: &Path, manifest: &toml::Table, file: &Path) -> Option<CargoTarget> {
+    let rel = rel_slash(file, crate_root)?;
+    let pkg_name = manifest.get("package")?.get("name")?.as_str()?.to_string();
+
+    let explicit = |section: &str, rel: &str| -> Option<String> {
+        manifest.get(section)?.as_array()?.iter().find_map(|t| {
+            let path = t.get("path")?.as_str()?.trim_start_matches("./");
+            (path == rel).then(|| t.get("name")?.as_str().map(str::to_string))?
+        })
+    };
+    if let Some(n) = explicit("bin", &rel) { return Some(CargoTarget::Bin(n)); }
+    if let Some(n) = explicit("example", &rel) { return Some(CargoTarget::Example(n)); }
+    if let Some(n) = explicit("test", &rel) { return Some(CargoTarget::Test(n)); }
+    if let Some(n) = explicit("bench", &rel) { return Some(CargoTarget::Bench(n)); }
+    // The main binary may be renamed with a `[[bin]]` entry pointing at src/main.rs
+    let main_bin = explicit("bin", "src/main.rs").unwrap_or(pkg_name);
+    let lib_path = manifest
+        .get("lib")
+        .and_then(|l| l.get("path"))
+        .and_then(|p| p.as_str())
+        .map(|p| p.trim_start_matches("./").to_string())
+        .unwrap_or_else(|| "src/lib.rs".into());
+    if rel == lib_path { return Some(CargoTarget::Lib); }
+    if rel == "build.rs" { return Some(CargoTarget::BuildScript); }
+
+    let parts: Vec<&str> = rel.split('/').collect();
+    let stem = |s: &str| s.trim_end_matches(".rs").to_string();
+    // `<dir>/<name>.rs` or `<dir>/<name>/**` (with a main.rs) name a target
+    let auto = |dir: &str| -> Option<Option<String>> {
+        match parts.as_slice() {
+            [d, f] if *d == dir => Some(Some(stem(f))),
+            [d, sub, ..] if *d == dir => {
+                Some(crate_root.join(dir).join(sub).join("main.rs").is_file().then(|| sub.to_string()))
+            }
+            _ => None,
+        }
+    };
+    match (parts.first().copied(), parts.get(1).copied()) {
+        (Some("src"), Some("main.rs")) if parts.len() == 2 => return Some(CargoTarget::Bin(main_bin)),
+        (Some("src"), Some("bin")) => {
+            let name = match &parts[2..] {
+                [f] => stem(f),
+                [sub, ..] => sub.to_string(),
+                [] => return None,
+            };
+            return Some(CargoTarget::Bin(name));
+        }
+        _ => {}
+    }
+    if let Some(n) = auto("examples") { return n.map(CargoTarget::Example); }
+    if let Some(n) = auto("tests") { return Some(n.map(CargoTarget::Test).unwrap_or(CargoTarget::Tests)); }
+    if let Some(n) = auto("benches") { return n.map(CargoTarget::Bench); }
+
+    // Any other module belongs to the library, or else to the main binary
+    if crate_root.join(&lib_path).is_file() {
+        Some(CargoTarget::Lib)
+    } else if crate_root.join("src/main.rs").is_file() {
+        Some(CargoTarget::Bin(main_bin))
+    } else {
+        None
+    }
+}
+
+/// Root of the workspace that lists `crate_root` as a member, if any.
+pub fn cargo_workspace(crate_root: &Path) -> Option<PathBuf> {
+    for dir in crate_root.ancestors() {
+        let Some(manifest) = read_manifest(&dir.join("Cargo.toml")) else { continue };
+        let Some(ws) = manifest.get("workspace") else { continue };
+        if dir == crate_root {
+            return Some(dir.to_path_buf());
+        }
+        let rel = rel_slash(crate_root, dir)?;
+        let listed = |key: &str| {
+            ws.get(key)
+                .and_then(|m| m.as_array())
+                .map(|m| m.iter().filter_map(|v| v.as_str()).any(|pat| crate::glob_match(pat.trim_end_matches('/'), &rel)))
+                .unwrap_or(false)
+        };
+        // The nearest workspace decides; a crate it does not list stands alone
+        return (listed("members") && !listed("exclude")).then(|| dir.to_path_buf());
+    }
+    None
+}
+
+/// `cargo` invocation for the target `file` belongs to. Types: build (default),
+/// check, clippy, doc, run, test, bench.
+pub fn cargo_command(p: &ProjectRoot, file: &Path, ty: Option<&str>) -> Option<(String, PathBuf)> {
+    let action = match ty.map(normalize_type).as_deref() {
+        None | Some("build") => "build",
+        Some(a @ ("check" | "clippy" | "doc" | "run" | "test" | "bench")) => a,
+        Some(_) => return None,
+    }
+    .to_string();
+    let crate_root = &p.root;
+    let manifest = read_manifest(&p.marker)?;
+    if manifest.get("package").is_none() {
+        // Virtual workspace manifest: act on the whole workspace
+        return Some((format!("cargo {}", action), crate_root.clone()));
+    }
+    let pkg = manifest.get("package")?.get("name")?.as_str()?.to_string();
+    let target = cargo_target(crate_root, &manifest, file);
+    let workspace = cargo_workspace(crate_root);
+
+    let (action, selector) = match (action.as_str(), &target) {
+        ("run", Some(t @ (CargoTarget::Bin(_) | CargoTarget::Example(_)))) => ("run", t.selector()),
+        ("run", Some(t @ (CargoTarget::Test(_) | CargoTarget::Tests))) => ("test", t.selector()),
+        ("run", Some(t @ CargoTarget::Bench(_))) => ("bench", t.selector()),
+        ("run", _) => ("run", String::new()),
+        ("doc", _) => ("doc", String::new()),
+        ("bench", Some(t @ CargoTarget::Bench(_))) => ("bench", t.selector()),
+        ("bench", _) => ("bench", String::new()),
+        (a, Some(t)) => (a, t.selector()),
+        (a, None) => (a, String::new()),
+    };
+    let mut cmd = format!("cargo {}", action);
+    if workspace.is_some() {
+        cmd.push_str(&format!(" -p {}", pkg));
+    }
+    if !selector.is_empty() {
+        cmd.push(' ');
+        cmd.push_str(&selector);
+    }
+    Some((cmd, workspace.unwrap_or_else(|| crate_root.clone())))
+}
+
+// ── Go ──────────────────────────────────────────────────────────────────────
+
+/// The Go package a file belongs to, inside its module.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GoPackage {
+    /// Directory holding go.mod
+    pub module_root: PathBuf,
+    /// Package directory relative to the module root, as `./cmd/tool` (or `.`)
+    pub path: String,
+    /// Declared package name (`main`, `foo`, `foo_test`, ...)
+    pub name: Option<String>,
+}
+
+/// Package clause of a Go source file.
+fn go_package_name(file: &Path) -> Option<String> {
+    let text = std::fs::read_to_string(file).ok()?;
+    text.lines()
+        .find_map(|l| l.trim().strip_prefix("package "))
+        .and_then(|rest| rest.split_whitespace().next())
+        .map(|name| name.to_string())
+}
+
+/// Package of `file` (an absolute path) in the nearest module above it.
+pub fn go_package(file: &Path) -> Option<GoPackage> {
+    let dir = file.parent()?;
+    let module_root = dir.ancestors().find(|d| d.join("go.mod").is_file())?.to_path_buf();
+    let rel = rel_slash(dir, &module_root)?;
+    let path = if rel.is_empty() { ".".to_string() } else { format!("./{}", rel) };
+    Some(GoPackage { module_root, path, name: go_package_name(file) })
+}
+
+/// Go commands scoped to the package containing `file`. Build and test run
+/// from the module root; a `package main` runs with `go run .` from its own
+/// directory, which is also what its default type does. `_test.go` files
+/// default to testing their package.
+fn go_command(p: &ProjectRoot, file: &Path, ty: Option<&str>) -> Option<(String, PathBuf)> {
+    let Some(pkg) = go_package(file) else {
+        return project_root_command(p, ty).map(|c| (c, p.root.clone()));
+    };
+    let is_main = pkg.name.as_deref() == Some("main");
+    let is_test = file.file_name().is_some_and(|n| n.to_string_lossy().ends_with("_test.go"));
+    let action = match ty.map(normalize_type) {
+        None if is_test => "test".to_string(),
+        None if is_main => "run".to_string(),
+        None => "build".to_string(),
+        Some(t) => t,
+    };
+    let root = pkg.module_root.clone();
+    match action.as_str() {
+        "build" => Some((format!("go build {}", pkg.path), root)),
+        "test" => Some((format!("go test {}", pkg.path), root)),
+        "vet" => Some((format!("go vet {}", pkg.path), root)),
+        "run" if is_main => Some(("go run .".to_string(), file.parent()?.to_path_buf())),
+        _ => None,
+    }
+}
+
+// ── Python ──────────────────────────────────────────────────────────────────
+
+/// How Python code of a project is run.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PyRunner {
+    Uv,
+    Poetry,
+    Pdm,
+    Hatch,
+    /// Interpreter of a project-local virtualenv
+    Venv(PathBuf),
+    System,
+}
+
+impl PyRunner {
+    /// Command running the interpreter, for `{{py}}`.
+    pub fn python(&self) -> String {
+        match self {
+            PyRunner::Uv => "uv run python".into(),
+            PyRunner::Poetry => "poetry run python".into(),
+            PyRunner::Pdm => "pdm run python".into(),
+            PyRunner::Hatch => "hatch run python".into(),
+            PyRunner::Venv(py) => format!("\"{}\"", py.display()),
+            PyRunner::System => if cfg!(windows) { "py" } else { "python3" }.into(),
+        }
+    }
+
+    /// Prefix running a tool inside the environment, for `{{py_run}}`
+    /// (`uv run pytest`, or `python -m pytest` for plain interpreters).
+    pub fn run_prefix(&self) -> String {
+        match self {
+            PyRunner::Uv => "uv run".into(),
+            PyRunner::Poetry => "poetry run".into(),
+            PyRunner::Pdm => "pdm run".into(),
+            PyRunner::Hatch => "hatch run".into(),
+            PyRunner::Venv(_) | PyRunner::System => format!("{} -m", self.python()),
+        }
+    }
+
+    fn build(&self) -> String {
+        match self {
+            PyRunner::Uv => "uv build".into(),
+            PyRunner::Poetry => "poetry build".into(),
+            PyRunner::Pdm => "pdm build".into(),
+            PyRunner::Hatch => "hatch build".into(),
+            PyRunner::Venv(_) | PyRunner::System => format!("{} build", self.run_prefix()),
+        }
+    }
+}
+
+/// Interpreter of a virtualenv directory, if it has one.
+fn venv_python(venv: &Path) -> Option<PathBuf> {
+    let py = if cfg!(windows) { venv.join("Scripts").join("python.exe") } else { venv.join("bin").join("python") };
+    py.exists().then_some(py)
+}
+
+/// Python runner for code in `dir`. Walks up to the project: at each level a
+/// lockfile (`uv.lock`, `poetry.lock`, `pdm.lock`) wins, then a `[tool.uv]`,
+/// `[tool.poetry]`, `[tool.pdm]` or `[tool.hatch]` section of pyproject.toml,
+/// then a local `.venv`/`venv`. The walk stops at a pyproject.toml or `.git`.
+pub fn pick_python_runner(dir: &Path) -> PyRunner {
+    for d in dir.ancestors() {
+        let has = |name: &str| d.join(name).exists();
+        if has("uv.lock") { return PyRunner::Uv; }
+        if has("poetry.lock") { return PyRunner::Poetry; }
+        if has("pdm.lock") { return PyRunner::Pdm; }
+        let pyproject = read_manifest(&d.join("pyproject.toml"));
+        if let Some(tool) = pyproject.as_ref().and_then(|m| m.get("tool")) {
+            if tool.get("uv").is_some() { return PyRunner::Uv; }
+            if tool.get("poetry").is_some() { return PyRunner::Poetry; }
+            if tool.get("pdm").is_some() { return PyRunner::Pdm; }
+            if tool.get("hatch").is_some() { return PyRunner::Hatch; }
+        }
+        if let Some(py) = [".venv", "venv"].iter().find_map(|v| venv_python(&d.join(v))) {
+            return PyRunner::Venv(py);
+        }
+        if pyproject.is_some() || has(".git") {
+            break;
+        }
+    }
+    PyRunner::System
+}
+
+/// Python project commands: a plain build (or `-run`) runs the file itself
+/// from its own directory with the project's runner; `-build` packages and
+/// `-test` tests the project from its root.
+fn python_command(p: &ProjectRoot, file: &Path, ty: Option<&str>) -> Option<(String, PathBuf)> {
+    if !matches!(ty.map(normalize_type).as_deref(), None | Some("run")) {
+        return project_root_command(p, ty).map(|c| (c, p.root.clone()));
+    }
+    let dir = file.parent()?;
+    let name = file.file_name()?.to_string_lossy();
+    Some((format!("{} \"{}\"", pick_python_runner(dir).python(), name), dir.to_path_buf()))
+}